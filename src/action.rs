@@ -1,17 +1,19 @@
-use core::panic;
 use std::collections::HashMap;
 
 use crate::ability::Ability;
+use crate::condition::Condition;
+use crate::damage::{Damage, DamageType};
 use crate::fight::Fight;
 use crate::formula::Formula;
-use crate::modifier::ModifierType;
 use crate::monster::*;
-use crate::resource::Charge;
-use crate::resource::Resource;
+use crate::resource::{ActionCost, Charge};
+use crate::resource::{consume_resources, has_resources, Resource};
+use crate::spell::SpellInfo;
 use crate::template::ActionTemplate;
+use crate::template::EffectTemplate;
 use crate::template::TemplateBuilder;
 use crate::utils::*;
-use rand::distributions::{Distribution, Uniform};
+use rand::Rng;
 use serde::{Deserialize, Serialize};
 
 #[derive(Default, Copy, Clone, Debug, Serialize, Deserialize)]
@@ -22,52 +24,100 @@ pub enum ActionCondition {
     HitCondition {
         #[serde(default)]
         attack_modifier: i32,
+        //Lowest natural roll scoring a critical hit
+        #[serde(default = "default_critical_range")]
+        critical_range: i32,
+        //Dice added to the first damage die on a critical hit (Savage Attacks, Brutal Critical)
+        #[serde(default)]
+        critical_extra_dice: i32,
+        #[serde(default)]
+        ranged: bool,
     },
+    //Succeeds when the target fails its saving throw
     SaveCondition {
         #[serde(default)]
         save_dc: i32,
         ability: Ability,
     },
 }
+fn default_critical_range() -> i32 {
+    20
+}
+enum Outcome {
+    Failure,
+    Success,
+    Critical { extra_dice: i32 },
+}
 impl ActionCondition {
     pub fn set_hit_roll(&mut self, attack_modifier: i32) {
+        if let Self::HitCondition {
+            attack_modifier: am,
+            ..
+        } = self
+        {
+            *am = attack_modifier;
+        }
+    }
+    //Chance for a hit roll to be a critical hit
+    fn critical_chance(&self) -> f32 {
         match self {
-            Self::HitCondition {
-                attack_modifier: am,
-            } => {
-                *am = attack_modifier;
-            }
-            _ => {}
+            Self::HitCondition { critical_range, .. } => (21 - critical_range).clamp(1, 20) as f32 / 20.0,
+            _ => 0.0,
         }
     }
     pub fn set_save_dc(&mut self, save_dc: i32) {
-        match self {
-            Self::SaveCondition { save_dc: sd, .. } => *sd = save_dc,
-            _ => {}
+        if let Self::SaveCondition { save_dc: sd, .. } = self {
+            *sd = save_dc;
         }
     }
-    fn pass(&self, fight: &mut Fight, source_id : i32, target_id: i32) -> bool {
+    //`stakes` is what the target loses on a failed save
+    fn pass(&self, fight: &mut Fight, source_id : i32, target_id: i32, stakes: Stakes) -> Outcome {
         match self {
-            Self::True => true,
-            Self::False => false,
+            Self::True => Outcome::Success,
+            Self::False => Outcome::Failure,
             Self::SaveCondition { save_dc, ability } => {
-                let save_mod = fight.get_modifier(target_id, ModifierType::Save(*ability));
-                let hit = save_mod.roll();
-                eprintln!("Save {hit} (DC {save_dc})");
-                hit >= *save_dc
-            }
-            Self::HitCondition { attack_modifier } => {
-                let mod_attacked = fight.get_modifier(target_id, ModifierType::Attacked);
-                let mod_attack = fight.get_modifier(source_id, ModifierType::Attack);
-                let mod_final = mod_attacked + mod_attack;
-                let throw = mod_final.roll();
-                let hit = throw + attack_modifier;
-                let ac = fight.get_ac(target_id);
+                if fight.roll_save(target_id, *ability, *save_dc, stakes) {
+                    Outcome::Failure
+                } else {
+                    Outcome::Success
+                }
+            }
+            Self::HitCondition {
+                attack_modifier,
+                critical_range,
+                critical_extra_dice,
+                ranged,
+            } => {
+                let mod_final = fight.get_attack_modifier(source_id, target_id, *ranged);
+                let throw = mod_final.roll(fight.rng());
+                let natural = throw.natural();
+                let hit = throw.total() + attack_modifier;
+                let Some(ac) = fight.get_ac(target_id) else {
+                    return Outcome::Failure;
+                };
                 eprintln!(
-                    "Roll {throw}+{attack_modifier} = {hit} (AC {})",
+                    "Roll {}+{attack_modifier} = {hit} (AC {})",
+                    throw.total(),
                     ac
                 );
-                hit >= ac
+                //A natural 1 always misses, and a critical hit always hits.
+                //Otherwise the target may react to a hit to turn it into a miss, like with Shield
+                if natural == 1
+                    || (natural < *critical_range && hit >= ac && fight.react_to_hit(target_id, hit - ac))
+                {
+                    Outcome::Failure
+                } else if natural >= *critical_range
+                    || (hit >= ac && fight.suffers_critical(target_id, *ranged))
+                {
+                    eprintln!("Critical hit");
+                    Outcome::Critical {
+                        extra_dice: *critical_extra_dice,
+                    }
+                } else if hit >= ac {
+                    Outcome::Success
+                } else {
+                    Outcome::Failure
+                }
             }
         }
     }
@@ -90,16 +140,70 @@ pub enum ActionComponent {
     Damage {
         #[serde(deserialize_with = "string_or_struct")]
         damage: Formula,
+        damage_type: DamageType,
+        //Spells and magic weapons overcome the resistances to nonmagical attacks
+        #[serde(default)]
+        magical: bool,
         #[serde(default)]
         rolled: i32,
+        //Set on the copy of the component dealing a critical hit
+        #[serde(skip)]
+        critical: bool,
     },
     HalfDamage {
         #[serde(deserialize_with = "string_or_struct")]
         damage: Formula,
+        damage_type: DamageType,
+        //Spells and magic weapons overcome the resistances to nonmagical attacks
+        #[serde(default)]
+        magical: bool,
         #[serde(default)]
         rolled: i32,
     },
-    //TODO add effect
+    Heal {
+        #[serde(deserialize_with = "string_or_struct")]
+        heal: Formula,
+        //Add the spellcasting modifier of the caster, like Cure Wounds
+        #[serde(default)]
+        spell_modifier: bool,
+        #[serde(default = "default_target_count")]
+        target_count: i32,
+        #[serde(default)]
+        rolled: i32,
+    },
+    //Temporary hit points for the creature using the action, like False Life
+    TempHp {
+        #[serde(deserialize_with = "string_or_struct")]
+        temp_hp: Formula,
+        #[serde(default)]
+        spell_modifier: bool,
+        #[serde(default)]
+        rolled: i32,
+    },
+    //Give an effect, with its conditions and modifiers, to the target
+    ApplyEffect {
+        effect: EffectTemplate,
+    },
+    //End the effects of the spells on an ally, like Dispel Magic.
+    //The spells of a higher level than `level` need an ability check.
+    Dispel {
+        #[serde(default)]
+        level: i32,
+        #[serde(default)]
+        ability_modifier: i32,
+    },
+}
+fn default_target_count() -> i32 {
+    1
+}
+/// Side of the fight an action component is used on.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Target {
+    Enemy,
+    Ally,
+    Myself,
+    //Allies under the spells of their enemies
+    Hindered,
 }
 
 impl ActionComponent {
@@ -125,6 +229,11 @@ impl ActionComponent {
     }
     pub fn set_save_dc(&mut self, save_dc: i32) {
         match self {
+            Self::ApplyEffect { effect } => {
+                if let Some(repeat_save) = &mut effect.repeat_save {
+                    repeat_save.save_dc = save_dc;
+                }
+            }
             Self::Condition {
                 condition,
                 success,
@@ -160,14 +269,187 @@ impl ActionComponent {
                     comp.set_target_count(target_count);
                 }
             }
+            Self::Heal {
+                target_count: tc, ..
+            } => *tc = target_count,
+            _ => {}
+        }
+    }
+    //The component dealing the damage of a critical hit: the damage dice are rolled twice
+    fn critical<R: Rng + ?Sized>(&self, extra_dice: i32, rng: &mut R) -> Self {
+        let mut critical = self.clone();
+        critical.roll_critical(extra_dice, rng);
+        critical
+    }
+    fn roll_critical<R: Rng + ?Sized>(&mut self, extra_dice: i32, rng: &mut R) {
+        match self {
+            Self::Damage {
+                damage,
+                rolled,
+                critical,
+                ..
+            } => {
+                *rolled += damage.critical(extra_dice).roll(rng);
+                *critical = true;
+            }
+            Self::Condition {
+                success, failure, ..
+            } => {
+                success.roll_critical(extra_dice, rng);
+                failure.roll_critical(extra_dice, rng);
+            }
+            Self::MultiComponent { next } => {
+                //The riders add dice to a single damage of the attack
+                let mut extra_dice = extra_dice;
+                for comp in next {
+                    comp.roll_critical(extra_dice, rng);
+                    extra_dice = 0;
+                }
+            }
+            Self::HalfDamage { .. }
+            | Self::Heal { .. }
+            | Self::TempHp { .. }
+            | Self::ApplyEffect { .. }
+            | Self::Dispel { .. }
+            | Self::Nothing => {}
+        }
+    }
+    //Expected damage added by the critical hits
+    fn critical_dammage(&self, extra_dice: i32) -> f32 {
+        match self {
+            Self::Damage { damage, .. } => damage.critical(extra_dice).average_roll(),
+            Self::Condition { success, .. } => success.critical_dammage(extra_dice),
+            Self::MultiComponent { next } => next
+                .iter()
+                .enumerate()
+                .map(|(i, comp)| comp.critical_dammage(if i == 0 { extra_dice } else { 0 }))
+                .sum(),
+            Self::HalfDamage { .. }
+            | Self::Heal { .. }
+            | Self::TempHp { .. }
+            | Self::ApplyEffect { .. }
+            | Self::Dispel { .. }
+            | Self::Nothing => 0.0,
+        }
+    }
+    pub fn set_magical(&mut self) {
+        match self {
+            Self::Damage { magical, .. } | Self::HalfDamage { magical, .. } => *magical = true,
+            Self::Condition {
+                success, failure, ..
+            } => {
+                success.set_magical();
+                failure.set_magical();
+            }
+            Self::MultiComponent { next } => {
+                for comp in next {
+                    comp.set_magical();
+                }
+            }
+            Self::Heal { .. }
+            | Self::TempHp { .. }
+            | Self::ApplyEffect { .. }
+            | Self::Dispel { .. }
+            | Self::Nothing => {}
+        }
+    }
+    /// Make the effects given by the component end with the concentration of their source.
+    pub fn set_concentration(&mut self) {
+        match self {
+            Self::ApplyEffect { effect } => effect.concentration = true,
+            Self::Condition {
+                success, failure, ..
+            } => {
+                success.set_concentration();
+                failure.set_concentration();
+            }
+            Self::MultiComponent { next } => {
+                for comp in next {
+                    comp.set_concentration();
+                }
+            }
+            Self::Damage { .. }
+            | Self::HalfDamage { .. }
+            | Self::Heal { .. }
+            | Self::TempHp { .. }
+            | Self::Dispel { .. }
+            | Self::Nothing => {}
+        }
+    }
+    /// Set the level of the spell cast with the component, the spells it dispels without a check.
+    pub fn set_spell_level(&mut self, spell_level: i32) {
+        match self {
+            Self::Dispel { level, .. } => *level = spell_level,
+            Self::Condition {
+                success, failure, ..
+            } => {
+                success.set_spell_level(spell_level);
+                failure.set_spell_level(spell_level);
+            }
+            Self::MultiComponent { next } => {
+                for comp in next {
+                    comp.set_spell_level(spell_level);
+                }
+            }
+            _ => {}
+        }
+    }
+    /// Add the spellcasting modifier of the caster to the healing and the checks that use it.
+    pub fn set_spell_modifier(&mut self, modifier: i32) {
+        match self {
+            Self::Heal {
+                heal,
+                spell_modifier: true,
+                ..
+            }
+            | Self::TempHp {
+                temp_hp: heal,
+                spell_modifier: true,
+                ..
+            } => heal.add_fixed(modifier),
+            Self::Dispel {
+                ability_modifier, ..
+            } => *ability_modifier = modifier,
+            Self::Condition {
+                success, failure, ..
+            } => {
+                success.set_spell_modifier(modifier);
+                failure.set_spell_modifier(modifier);
+            }
+            Self::MultiComponent { next } => {
+                for comp in next {
+                    comp.set_spell_modifier(modifier);
+                }
+            }
+            _ => {}
+        }
+    }
+    pub fn increase_heal(&mut self, amount: &Formula) {
+        match self {
+            Self::Heal { heal, .. } | Self::TempHp { temp_hp: heal, .. } => *heal += amount,
+            Self::Condition {
+                success, failure, ..
+            } => {
+                success.increase_heal(amount);
+                failure.increase_heal(amount);
+            }
+            Self::MultiComponent { next } => {
+                for comp in next {
+                    comp.increase_heal(amount);
+                }
+            }
             _ => {}
         }
     }
     pub fn increase_damage(&mut self, damage: &Formula) {
         match self {
-            ActionComponent::Nothing => {}
+            ActionComponent::Nothing
+            | ActionComponent::Heal { .. }
+            | ActionComponent::TempHp { .. }
+            | ActionComponent::ApplyEffect { .. }
+            | ActionComponent::Dispel { .. } => {}
             ActionComponent::Damage { damage: dmg, .. }
-            | ActionComponent::HalfDamage { damage: dmg, .. } => *dmg += *damage,
+            | ActionComponent::HalfDamage { damage: dmg, .. } => *dmg += damage,
             ActionComponent::Condition {
                 success, failure, ..
             } => {
@@ -185,7 +467,11 @@ impl ActionComponent {
         match self {
             ActionComponent::Nothing
             | ActionComponent::Damage { .. }
-            | ActionComponent::HalfDamage { .. } => {}
+            | ActionComponent::HalfDamage { .. }
+            | ActionComponent::TempHp { .. }
+            | ActionComponent::ApplyEffect { .. }
+            | ActionComponent::Dispel { .. } => {}
+            ActionComponent::Heal { target_count, .. } => *target_count += amount,
             ActionComponent::Condition {
                 success,
                 failure,
@@ -205,54 +491,168 @@ impl ActionComponent {
     }
     pub fn average_dammage(&self) -> f32 {
         match self {
-            ActionComponent::Nothing => 0.0,
+            ActionComponent::Nothing
+            | ActionComponent::Heal { .. }
+            | ActionComponent::TempHp { .. }
+            | ActionComponent::ApplyEffect { .. }
+            | ActionComponent::Dispel { .. } => 0.0,
             ActionComponent::Damage { damage, .. } => damage.average_roll(),
-            ActionComponent::HalfDamage { damage, .. } => damage.average_roll() / 2.0,
-            ActionComponent::Condition { success, .. } => success.average_dammage(), //TODO take failure in account too
+            ActionComponent::HalfDamage { damage, .. } => {
+                damage.distribution().halved().mean() as f32
+            }
+            ActionComponent::Condition {
+                condition, success, ..
+            } => {
+                //TODO take failure in account too
+                let critical = match condition {
+                    ActionCondition::HitCondition {
+                        critical_extra_dice,
+                        ..
+                    } => condition.critical_chance() * success.critical_dammage(*critical_extra_dice),
+                    _ => 0.0,
+                };
+                success.average_dammage() + critical
+            }
             ActionComponent::MultiComponent { next } => {
                 next.iter().map(|comp| comp.average_dammage()).sum()
             }
         }
     }
-    pub fn ready_for_apply(&mut self) {
+    /// Expected hp restored by the component, given the missing hp of the allies it could heal,
+    /// most wounded first, with a weight for how urgent it is to heal them.
+    pub fn healing_value(&self, wounds: &[(i32, f32)]) -> f32 {
+        match self {
+            ActionComponent::Heal {
+                heal, target_count, ..
+            } => {
+                let average = heal.average_roll();
+                wounds
+                    .iter()
+                    .take(*target_count as usize)
+                    .map(|(missing, weight)| average.min(*missing as f32) * weight)
+                    .sum()
+            }
+            ActionComponent::Condition { success, .. } => success.healing_value(wounds),
+            ActionComponent::MultiComponent { next } => {
+                next.iter().map(|comp| comp.healing_value(wounds)).sum()
+            }
+            _ => 0.0,
+        }
+    }
+    /// Expected temporary hit points gained over the `current` ones, which they would replace.
+    pub fn temp_hp_value(&self, current: i32) -> f32 {
+        match self {
+            ActionComponent::TempHp { temp_hp, .. } => {
+                (temp_hp.average_roll() - current as f32).max(0.0)
+            }
+            ActionComponent::Condition { success, .. } => success.temp_hp_value(current),
+            ActionComponent::MultiComponent { next } => {
+                next.iter().map(|comp| comp.temp_hp_value(current)).sum()
+            }
+            _ => 0.0,
+        }
+    }
+    /// Worth of the spells the component could dispel, given how much they hinder each ally,
+    /// most hindered first.
+    pub fn dispel_value(&self, hindrances: &[f32]) -> f32 {
+        match self {
+            ActionComponent::Dispel { .. } => hindrances.first().copied().unwrap_or(0.0),
+            ActionComponent::Condition { success, .. } => success.dispel_value(hindrances),
+            ActionComponent::MultiComponent { next } => {
+                next.iter().map(|comp| comp.dispel_value(hindrances)).sum()
+            }
+            _ => 0.0,
+        }
+    }
+    /// Rough worth of the effects given by the component, counted like damage so the AI can
+    /// compare them: a condition taking the target out of the fight is worth 10, another one 2,
+    /// and each modifier 1.
+    pub fn average_effect(&self) -> f32 {
+        match self {
+            ActionComponent::ApplyEffect { effect } => {
+                let conditions = effect.conditions.iter().map(Condition::value).sum::<f32>();
+                conditions + effect.modifiers.len() as f32
+            }
+            ActionComponent::Condition { success, .. } => success.average_effect(),
+            ActionComponent::MultiComponent { next } => {
+                next.iter().map(|comp| comp.average_effect()).sum()
+            }
+            _ => 0.0,
+        }
+    }
+    pub fn ready_for_apply<R: Rng + ?Sized>(&mut self, rng: &mut R) {
         //ready_for_apply is useful to pre-roll dices so Fireball doesn't inflict different amount
         //of damage to each target.
         //TODO HalfDamage and Damage do not have synchronized rolled :'(.
         match self {
-            ActionComponent::Damage { damage, rolled } => {
-                *rolled = damage.roll();
+            ActionComponent::Damage { damage, rolled, .. } => {
+                *rolled = damage.roll(rng);
             }
-            ActionComponent::HalfDamage { damage, rolled } => {
-                *rolled = (damage.roll() as f32 / 2.0).floor() as i32;
+            ActionComponent::HalfDamage { damage, rolled, .. } => {
+                *rolled = (damage.roll(rng) as f32 / 2.0).floor() as i32;
+            }
+            ActionComponent::Heal { heal, rolled, .. }
+            | ActionComponent::TempHp {
+                temp_hp: heal,
+                rolled,
+                ..
+            } => {
+                *rolled = heal.roll(rng);
             }
             ActionComponent::Condition {
                 success, failure, ..
             } => {
-                success.ready_for_apply();
-                failure.ready_for_apply();
+                success.ready_for_apply(rng);
+                failure.ready_for_apply(rng);
             }
             ActionComponent::MultiComponent { next } => {
                 for comp in next {
-                    comp.ready_for_apply();
+                    comp.ready_for_apply(rng);
                 }
             }
-            ActionComponent::Nothing => {}
+            ActionComponent::ApplyEffect { .. }
+            | ActionComponent::Dispel { .. }
+            | ActionComponent::Nothing => {}
         }
     }
     pub fn apply(&self, source_id: i32, target_id: i32, fight: &mut Fight) {
         //Apply recursively all components to a target.
         match &self {
-            ActionComponent::Damage { rolled, .. } => {
-                let dmg = *rolled;
-                fight.decrease_hp(target_id, dmg);
-                let hp = fight.get_hp(target_id);
-                eprintln!("Dammage {dmg} -> hp target : {}", hp);
-            }
-            ActionComponent::HalfDamage { rolled, .. } => {
-                let dmg = *rolled;
-                fight.decrease_hp(target_id, dmg);
-                let hp = fight.get_hp(target_id);
-                eprintln!("HalfDmg {dmg} -> hp target : {}", hp);
+            ActionComponent::Damage {
+                rolled,
+                damage_type,
+                magical,
+                critical,
+                ..
+            } => {
+                let damage = Damage::new(*rolled, *damage_type)
+                    .magical(*magical)
+                    .critical(*critical);
+                fight.inflict_damage(source_id, target_id, damage);
+            }
+            ActionComponent::HalfDamage {
+                rolled,
+                damage_type,
+                magical,
+                ..
+            } => {
+                let damage = Damage::new(*rolled, *damage_type).magical(*magical);
+                fight.inflict_damage(source_id, target_id, damage);
+            }
+            ActionComponent::Heal { rolled, .. } => {
+                fight.heal(source_id, target_id, *rolled);
+            }
+            ActionComponent::TempHp { rolled, .. } => {
+                fight.add_temp_hp(target_id, *rolled);
+            }
+            ActionComponent::ApplyEffect { effect } => {
+                fight.apply_effect(effect, source_id, target_id);
+            }
+            ActionComponent::Dispel {
+                level,
+                ability_modifier,
+            } => {
+                fight.dispel(target_id, *level, *ability_modifier);
             }
             ActionComponent::Condition {
                 condition,
@@ -260,10 +660,17 @@ impl ActionComponent {
                 failure,
                 ..
             } => {
-                if condition.pass(fight, source_id, target_id) {
-                    success.apply(source_id, target_id, fight);
-                } else {
-                    failure.apply(source_id, target_id, fight);
+                let stakes = Stakes {
+                    damage: success.average_dammage() - failure.average_dammage(),
+                    effect: success.average_effect() - failure.average_effect(),
+                };
+                match condition.pass(fight, source_id, target_id, stakes) {
+                    Outcome::Success => success.apply(source_id, target_id, fight),
+                    Outcome::Critical { extra_dice } => {
+                        let critical = success.critical(extra_dice, fight.rng());
+                        critical.apply(source_id, target_id, fight);
+                    }
+                    Outcome::Failure => failure.apply(source_id, target_id, fight),
                 }
             }
             ActionComponent::MultiComponent { next } => {
@@ -280,20 +687,43 @@ impl ActionComponent {
             ActionComponent::Condition { target_count, .. } => *target_count as usize,
             ActionComponent::Damage { .. } | ActionComponent::HalfDamage { .. } => 1, //TODO does that makes sense?
             ActionComponent::MultiComponent { .. } => 1, //TODO does that makes sense?
+            ActionComponent::Heal { target_count, .. } => *target_count as usize,
+            ActionComponent::TempHp { .. }
+            | ActionComponent::ApplyEffect { .. }
+            | ActionComponent::Dispel { .. } => 1,
             ActionComponent::Nothing => 0,
         }
     }
+    pub fn target(&self) -> Target {
+        match &self {
+            ActionComponent::Heal { .. } => Target::Ally,
+            ActionComponent::TempHp { .. } => Target::Myself,
+            ActionComponent::Dispel { .. } => Target::Hindered,
+            ActionComponent::Condition { success, .. } => success.target(),
+            ActionComponent::MultiComponent { next } => {
+                next.first().map_or(Target::Enemy, |comp| comp.target())
+            }
+            _ => Target::Enemy,
+        }
+    }
 }
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct ActionStruct {
     charges: Charge,
     resources: Vec<Resource>,
     components: Vec<ActionComponent>, //Spell/Attack/MultiAttack/Object/
+    //Only for the spells, casting one with concentration ends the previous one
+    spell: Option<SpellInfo>,
+    //Taking the Attack action allows the bonus attacks
+    attack_action: bool,
 }
 impl ActionStruct {
     pub fn get_components(&self) -> &Vec<ActionComponent> {
         &self.components
     }
+    pub fn resources(&self) -> &[Resource] {
+        &self.resources
+    }
     pub fn from_template(
         builder: &TemplateBuilder,
         monster: &Monster,
@@ -302,66 +732,76 @@ impl ActionStruct {
         let mut ret = HashMap::new();
         match template {
             ActionTemplate::Attack {
-                attack_modifier,
-                dammage,
-                target_count,
                 name,
+                charges,
+                cost,
+                resources,
+                off_hand,
+                ..
             } => {
-                let dmg = ActionComponent::Damage {
-                    damage: *dammage,
-                    rolled: 0,
-                };
-                let component = ActionComponent::Condition {
-                    condition: ActionCondition::HitCondition {
-                        attack_modifier: *attack_modifier,
-                    },
-                    success: Box::new(dmg),
-                    failure: Box::new(ActionComponent::Nothing),
-                    target_count: *target_count,
+                let spent = if *off_hand {
+                    vec![Resource::BonusAction, Resource::AttackAction]
+                } else {
+                    cost.resources()
                 };
                 let action = ActionStruct {
-                    charges: Charge::Infinite,
-                    resources: vec![Resource::Action],
-                    components: vec![component],
+                    charges: *charges,
+                    resources: spent.into_iter().chain(resources.iter().copied()).collect(),
+                    components: vec![Self::attack_component(monster, template)],
+                    spell: None,
+                    attack_action: !off_hand && *cost == ActionCost::Action,
                 };
                 ret.insert(name.clone(), action);
             }
-            ActionTemplate::MultiAttack { attacks, name } => {
+            ActionTemplate::MultiAttack {
+                attacks,
+                name,
+                charges,
+                cost,
+                resources,
+            } => {
                 let components = attacks
                     .iter()
                     .map(|t| match t {
-                        ActionTemplate::Attack {
-                            attack_modifier,
-                            dammage,
-                            target_count,
-                            ..
-                        } => {
-                            let dmg = ActionComponent::Damage {
-                                damage: *dammage,
-                                rolled: 0,
-                            };
-                            let component = ActionComponent::Condition {
-                                condition: ActionCondition::HitCondition {
-                                    attack_modifier: *attack_modifier,
-                                },
-                                success: Box::new(dmg),
-                                failure: Box::new(ActionComponent::Nothing),
-                                target_count: *target_count,
-                            };
-                            component
-                        }
+                        ActionTemplate::Attack { .. } => Self::attack_component(monster, t),
                         ActionTemplate::MultiAttack { .. } => {
                             unreachable!("MultiAttack cannot be nested in the database.");
                         }
-                        ActionTemplate::Spell { .. } => {
-                            unreachable!("Spell cannot be in MultiAttack.");
+                        ActionTemplate::Spell { .. }
+                        | ActionTemplate::Feature { .. }
+                        | ActionTemplate::Legendary { .. } => {
+                            unreachable!("Only attacks can be in MultiAttack.");
                         }
                     })
                     .collect();
                 let action = ActionStruct {
-                    charges: Charge::Infinite,
-                    resources: vec![Resource::Action],
+                    charges: *charges,
+                    resources: cost.resources().into_iter().chain(resources.iter().copied()).collect(),
                     components,
+                    spell: None,
+                    attack_action: *cost == ActionCost::Action,
+                };
+                ret.insert(name.clone(), action);
+            }
+            ActionTemplate::Legendary { cost, action } => {
+                for (name, mut action) in Self::from_template(builder, monster, action) {
+                    action.resources = vec![Resource::LegendaryAction; *cost as usize];
+                    ret.insert(name, action);
+                }
+            }
+            ActionTemplate::Feature {
+                name,
+                components,
+                charges,
+                cost,
+                resources,
+            } => {
+                let action = ActionStruct {
+                    charges: *charges,
+                    resources: cost.resources().into_iter().chain(resources.iter().copied()).collect(),
+                    components: components.clone(),
+                    spell: None,
+                    attack_action: false,
                 };
                 ret.insert(name.clone(), action);
             }
@@ -369,21 +809,24 @@ impl ActionStruct {
                 name,
                 spell_attack,
                 spell_dc,
+                spell_modifier,
             } => {
                 //Build the spell from the template
                 let mut spell_template = builder.get_spell_template(name);
                 let highest = monster.highest_spell_slot();
                 let lowest = spell_template.get_base_level();
-                //Expend action based on how much the spell can be upcasted
+                //Expend action based on how much the spell can be upcasted, cantrips can't be
+                let highest = if lowest == 0 { 0 } else { highest };
                 for upcast_lvl in 0..=(highest - lowest) {
                     let action = spell_template
                         .spell_attack(*spell_attack)
                         .spell_dc(*spell_dc)
+                        .spell_modifier(*spell_modifier)
                         .upcast(upcast_lvl)
                         .build();
                     let mut name = name.clone();
                     let lvl = upcast_lvl + lowest;
-                    name.push_str(" ");
+                    name.push(' ');
                     name.push_str(&lvl.to_string());
                     ret.insert(name, action);
                 }
@@ -391,18 +834,97 @@ impl ActionStruct {
         }
         ret
     }
+    //Build the hit roll of an attack, dealing each of its damage on a hit
+    fn attack_component(monster: &Monster, template: &ActionTemplate) -> ActionComponent {
+        let ActionTemplate::Attack {
+            attack_modifier,
+            dammage,
+            damage_type,
+            additional_damage,
+            magical,
+            target_count,
+            critical_range,
+            critical_extra_dice,
+            ranged,
+            on_hit,
+            off_hand,
+            finesse,
+            ..
+        } = template
+        else {
+            unreachable!("Only attacks have a hit roll.");
+        };
+        let mut dammage = dammage.clone();
+        //The off-hand attack doesn't add the ability modifier, unless it is negative
+        if *off_hand {
+            let strength = monster.ability_mod(Ability::Strength);
+            let dexterity = monster.ability_mod(Ability::Dexterity);
+            let ability_modifier = match (*ranged, *finesse) {
+                (true, _) => dexterity,
+                (false, true) => strength.max(dexterity),
+                (false, false) => strength,
+            };
+            if ability_modifier > 0 {
+                dammage.add_fixed(-ability_modifier);
+            }
+        }
+        let mut damages = vec![ActionComponent::Damage {
+            damage: dammage,
+            damage_type: *damage_type,
+            magical: *magical,
+            rolled: 0,
+            critical: false,
+        }];
+        damages.extend(additional_damage.iter().map(|dmg| ActionComponent::Damage {
+            damage: dmg.damage.clone(),
+            damage_type: dmg.damage_type,
+            magical: *magical,
+            rolled: 0,
+            critical: false,
+        }));
+        damages.extend(on_hit.iter().cloned());
+        let success = if damages.len() == 1 {
+            damages.pop().unwrap()
+        } else {
+            ActionComponent::MultiComponent { next: damages }
+        };
+        ActionComponent::Condition {
+            condition: ActionCondition::HitCondition {
+                attack_modifier: *attack_modifier,
+                //The action overrides the critical hits of the monster
+                critical_range: critical_range
+                    .or(monster.critical_range())
+                    .unwrap_or_else(default_critical_range),
+                critical_extra_dice: critical_extra_dice
+                    .unwrap_or_else(|| monster.critical_extra_dice()),
+                ranged: *ranged,
+            },
+            success: Box::new(success),
+            failure: Box::new(ActionComponent::Nothing),
+            target_count: *target_count,
+        }
+    }
     pub fn set_charge(&mut self, charge: Charge) {
         self.charges = charge;
     }
     pub fn add_resource(&mut self, resource: Resource) {
         self.resources.push(resource);
     }
+    pub fn set_spell(&mut self, spell: SpellInfo) {
+        self.spell = Some(spell);
+    }
+    pub fn spell(&self) -> Option<&SpellInfo> {
+        self.spell.as_ref()
+    }
+    pub fn is_concentration(&self) -> bool {
+        self.spell.as_ref().is_some_and(|spell| spell.concentration)
+    }
     pub fn add_component(&mut self, component: ActionComponent) {
         self.components.push(component);
     }
-    pub fn ready_for_apply(&mut self) {
+    pub fn ready_for_apply<R: Rng + ?Sized>(&mut self, rng: &mut R) {
         for comp in &mut self.components {
-            comp.ready_for_apply();
+            comp.ready_for_apply(rng);
         }
     }
     pub fn average_dammage(&self) -> f32 {
@@ -411,33 +933,67 @@ impl ActionStruct {
             .map(|component| component.average_dammage())
             .sum()
     }
+    pub fn healing_value(&self, wounds: &[(i32, f32)]) -> f32 {
+        self.components
+            .iter()
+            .map(|component| component.healing_value(wounds))
+            .sum()
+    }
+    pub fn average_effect(&self) -> f32 {
+        self.components
+            .iter()
+            .map(|component| component.average_effect())
+            .sum()
+    }
+    pub fn temp_hp_value(&self, current: i32) -> f32 {
+        self.components
+            .iter()
+            .map(|component| component.temp_hp_value(current))
+            .sum()
+    }
+    pub fn dispel_value(&self, hindrances: &[f32]) -> f32 {
+        self.components
+            .iter()
+            .map(|component| component.dispel_value(hindrances))
+            .sum()
+    }
     pub fn consume_resources(&self, resources: &mut HashMap<Resource, i32>) {
-        for resource in &self.resources {
-            resources.entry(*resource).and_modify(|qty| *qty -= 1);
-        }
+        consume_resources(&self.resources, resources);
     }
     pub fn is_available(&self, resources: &HashMap<Resource, i32>) -> bool {
-        self.resources.iter().all(|resource| {
-            if let Some(qty) = resources.get(resource) {
-                *qty > 0
-            } else {
-                false
-            }
-        })
+        has_resources(&self.resources, resources)
+    }
+    /// Level of the spell cast by the action, 0 for a cantrip, `None` if it isn't a spell.
+    pub fn spell_level(&self) -> Option<i32> {
+        self.spell.as_ref().map(|spell| spell.level)
+    }
+    /// Whether playing the action is taking the Attack action.
+    pub fn is_attack_action(&self) -> bool {
+        self.attack_action
+    }
+    /// Whether the action costs nothing, it is then played once per turn.
+    pub fn is_free(&self) -> bool {
+        self.resources.is_empty()
+    }
+    /// Whether the action is played with legendary actions, at the end of the turn of another creature.
+    pub fn is_legendary(&self) -> bool {
+        self.resources.contains(&Resource::LegendaryAction)
     }
     pub fn has_charges(&self) -> bool {
-        match self.charges {
-            Charge::Infinite => true,
-            Charge::Limited(qty) => qty > 0,
-        }
+        self.charges.is_available()
     }
     pub fn use_charge(&mut self) {
-        match &mut self.charges {
-            Charge::Infinite => {}
-            Charge::Limited(qty) => {
-                *qty -= 1;
-            }
-        }
+        self.charges.use_charge();
+    }
+    /// Roll the recharge of the action at the start of the turn, return whether it is available again.
+    pub fn roll_recharge<R: Rng + ?Sized>(&mut self, rng: &mut R) -> bool {
+        self.charges.roll_recharge(rng)
+    }
+    pub fn short_rest(&mut self) {
+        self.charges.short_rest();
+    }
+    pub fn long_rest(&mut self) {
+        self.charges.long_rest();
     }
 }
 impl Default for ActionStruct {
@@ -446,6 +1002,116 @@ impl Default for ActionStruct {
             charges: Charge::Infinite,
             resources: vec![],
             components: vec![],
+            spell: None,
+            attack_action: false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn mixed_damage() {
+        let template: ActionTemplate = serde_json::from_str(
+            r#"{"Attack" : {"name": "Flame Tongue", "attack_modifier" : 5, "dammage": "2d6+3", "damage_type": "Slashing",
+                "additional_damage": [{"damage": "2d6", "damage_type": "Fire"}], "target_count" : 1}}"#,
+        )
+        .unwrap();
+        let actions =
+            ActionStruct::from_template(&TemplateBuilder::default(), &Monster::default(), &template);
+        let action = &actions["Flame Tongue"];
+        //17 on a hit, and the 14 of the dice rolled again on the 5% of critical hits
+        assert!((action.average_dammage() - 17.7).abs() < 1e-5);
+        let ActionComponent::Condition { success, .. } = &action.get_components()[0] else {
+            panic!("An attack starts with its hit roll");
+        };
+        let ActionComponent::MultiComponent { next } = success.as_ref() else {
+            panic!("A mixed damage attack deals several damage");
+        };
+        let types = next
+            .iter()
+            .filter_map(|comp| match comp {
+                ActionComponent::Damage { damage_type, .. } => Some(*damage_type),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(types, vec![DamageType::Slashing, DamageType::Fire]);
+    }
+    #[test]
+    fn critical_hit() {
+        let template: ActionTemplate = serde_json::from_str(
+            r#"{"Attack" : {"name": "Greataxe", "attack_modifier" : -20, "dammage": "1d12+3", "damage_type": "Slashing",
+                "critical_range": 2, "target_count" : 1}}"#,
+        )
+        .unwrap();
+        let mut monster = Monster::default();
+        monster.set_team(1);
+        let mut action = ActionStruct::from_template(&TemplateBuilder::default(), &monster, &template)
+            .remove("Greataxe")
+            .unwrap();
+        let mut target = Monster::default();
+        target.set_hp(100);
+        let mut fight = Fight::with_seed(vec![monster, target], 2);
+        //Only a natural 1 misses, and every hit doubles the damage die
+        let mut hits = 0;
+        for _ in 0..20 {
+            action.ready_for_apply(fight.rng());
+            let hp = fight.get_hp(1).unwrap();
+            action.get_components()[0].apply(0, 1, &mut fight);
+            let lost = hp - fight.get_hp(1).unwrap();
+            if lost > 0 {
+                hits += 1;
+                assert!((5..=27).contains(&lost), "{lost}");
+            }
+            fight.increase_hp(1, 100);
         }
+        assert!(hits >= 15);
+    }
+    #[test]
+    fn off_hand() {
+        let monsters = serde_json::from_str(include_str!("../gobelin.json")).unwrap();
+        let spells = serde_json::from_str(include_str!("../spells.json")).unwrap();
+        let mut builder = TemplateBuilder::new(monsters, spells);
+        //Strength 10 and dexterity 16
+        let monk = builder.create("Monk").build();
+        //Strength 8 and dexterity 14
+        let kelani = builder.create("Kelani").build();
+        let off_hand = |monster: &Monster, json: &str| {
+            let template: ActionTemplate = serde_json::from_str(json).unwrap();
+            ActionStruct::from_template(&builder, monster, &template).remove("Off-hand").unwrap()
+        };
+        let dagger = off_hand(
+            &monk,
+            r#"{"Attack" : {"name": "Off-hand", "attack_modifier" : 6, "dammage": "1d4+4", "damage_type": "Piercing",
+                "target_count" : 1, "off_hand": true, "finesse": true, "magical": true}}"#,
+        );
+        //A bonus action after the Attack action
+        let mut resources = HashMap::from([(Resource::Action, 1), (Resource::BonusAction, 1)]);
+        assert!(!dagger.is_available(&resources));
+        assert!(!dagger.is_attack_action());
+        resources.insert(Resource::AttackAction, 1);
+        assert!(dagger.is_available(&resources));
+        let damage = |action: &ActionStruct| {
+            let ActionComponent::Condition { success, .. } = &action.get_components()[0] else {
+                panic!("An attack starts with its hit roll");
+            };
+            success.average_dammage()
+        };
+        //Without the dexterity modifier, but with the +1 of the dagger
+        assert_eq!(damage(&dagger), 3.5);
+        let dagger = off_hand(
+            &kelani,
+            r#"{"Attack" : {"name": "Off-hand", "attack_modifier" : 4, "dammage": "1d4+2", "damage_type": "Piercing",
+                "target_count" : 1, "off_hand": true, "finesse": true}}"#,
+        );
+        assert_eq!(damage(&dagger), 2.5);
+        //A negative modifier is still added
+        let handaxe = off_hand(
+            &kelani,
+            r#"{"Attack" : {"name": "Off-hand", "attack_modifier" : 1, "dammage": "1d6-1", "damage_type": "Slashing",
+                "target_count" : 1, "off_hand": true}}"#,
+        );
+        assert_eq!(damage(&handaxe), 2.5);
     }
 }
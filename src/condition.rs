@@ -0,0 +1,126 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+use crate::ability::Ability;
+use crate::modifier::{Modifier, ModifierType};
+
+/// The 5e conditions. Only what changes the rolls and the turns is simulated: there are no
+/// positions, so the melee attacks are made within 5 ft and the ranged ones further.
+/// Charmed, Deafened, Exhaustion and Grappled are tracked but change nothing yet.
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq, Serialize, Deserialize)]
+pub enum Condition {
+    Blinded,
+    Charmed,
+    Deafened,
+    Exhaustion,
+    Frightened,
+    Grappled,
+    Incapacitated,
+    Invisible,
+    Paralyzed,
+    Petrified,
+    Poisoned,
+    Prone,
+    Restrained,
+    Stunned,
+    Unconscious,
+}
+
+impl Condition {
+    /// Conditions that come with this one, like the incapacitation of a stunned creature.
+    pub fn implies(&self) -> &'static [Condition] {
+        match self {
+            Self::Paralyzed | Self::Petrified | Self::Stunned => &[Self::Incapacitated],
+            Self::Unconscious => &[Self::Incapacitated, Self::Prone],
+            _ => &[],
+        }
+    }
+    /// Modifier of the rolls of type `mod_type` of the creature with the condition.
+    /// `ranged` tells if the attack made or received is a ranged one.
+    pub fn modifier(&self, mod_type: ModifierType, ranged: bool) -> Modifier {
+        let mut modifier = Modifier::new(mod_type);
+        match (self, mod_type) {
+            (
+                Self::Blinded
+                | Self::Frightened
+                | Self::Poisoned
+                | Self::Prone
+                | Self::Restrained,
+                ModifierType::Attack,
+            ) => modifier.add_disadvantage(),
+            (Self::Invisible, ModifierType::Attack) => modifier.add_advantage(),
+            (
+                Self::Blinded
+                | Self::Paralyzed
+                | Self::Petrified
+                | Self::Restrained
+                | Self::Stunned
+                | Self::Unconscious,
+                ModifierType::Attacked,
+            ) => modifier.add_advantage(),
+            (Self::Invisible, ModifierType::Attacked) => modifier.add_disadvantage(),
+            (Self::Prone, ModifierType::Attacked) => {
+                if ranged {
+                    modifier.add_disadvantage();
+                } else {
+                    modifier.add_advantage();
+                }
+            }
+            (Self::Restrained, ModifierType::Save(Ability::Dexterity)) => {
+                modifier.add_disadvantage()
+            }
+            _ => {}
+        }
+        modifier
+    }
+    /// How much giving the condition is worth to the AI, losing the turns is worth the most.
+    pub fn value(&self) -> f32 {
+        if *self == Self::Incapacitated || self.implies().contains(&Self::Incapacitated) {
+            10.0
+        } else {
+            2.0
+        }
+    }
+    /// Whether the creature fails its saving throws of `ability` without rolling.
+    pub fn fails_save(&self, ability: Ability) -> bool {
+        matches!(
+            self,
+            Self::Paralyzed | Self::Petrified | Self::Stunned | Self::Unconscious
+        ) && matches!(ability, Ability::Strength | Ability::Dexterity)
+    }
+    /// Whether the hits of an attack against the creature are critical hits.
+    pub fn suffers_critical(&self, ranged: bool) -> bool {
+        !ranged && matches!(self, Self::Paralyzed | Self::Unconscious)
+    }
+}
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{self:?}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn prone() {
+        let melee = Condition::Prone.modifier(ModifierType::Attacked, false);
+        let ranged = Condition::Prone.modifier(ModifierType::Attacked, true);
+        assert!(melee.has_advantage());
+        assert!(ranged.has_disadvantage());
+        assert!(Condition::Prone
+            .modifier(ModifierType::Attack, false)
+            .has_disadvantage());
+    }
+    #[test]
+    fn saves() {
+        assert!(Condition::Stunned.fails_save(Ability::Dexterity));
+        assert!(Condition::Paralyzed.fails_save(Ability::Strength));
+        assert!(!Condition::Paralyzed.fails_save(Ability::Wisdom));
+        assert!(!Condition::Poisoned.fails_save(Ability::Strength));
+        assert!(Condition::Restrained
+            .modifier(ModifierType::Save(Ability::Dexterity), false)
+            .has_disadvantage());
+        assert!(Condition::Unconscious.implies().contains(&Condition::Incapacitated));
+    }
+}
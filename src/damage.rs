@@ -0,0 +1,85 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+#[derive(Default, Copy, Clone, Debug, Eq, Hash, PartialEq, Serialize, Deserialize)]
+pub enum DamageType {
+    Acid,
+    #[default]
+    Bludgeoning,
+    Cold,
+    Fire,
+    Force,
+    Lightning,
+    Necrotic,
+    Piercing,
+    Poison,
+    Psychic,
+    Radiant,
+    Slashing,
+    Thunder,
+}
+impl fmt::Display for DamageType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Acid => "acid",
+            Self::Bludgeoning => "bludgeoning",
+            Self::Cold => "cold",
+            Self::Fire => "fire",
+            Self::Force => "force",
+            Self::Lightning => "lightning",
+            Self::Necrotic => "necrotic",
+            Self::Piercing => "piercing",
+            Self::Poison => "poison",
+            Self::Psychic => "psychic",
+            Self::Radiant => "radiant",
+            Self::Slashing => "slashing",
+            Self::Thunder => "thunder",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// An amount of damage of a single type, as it is dealt to a creature.
+#[derive(Default, Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Damage {
+    amount: i32,
+    damage_type: DamageType,
+    magical: bool,
+    critical: bool,
+}
+impl Damage {
+    /// Nonmagical damage, see `magical` for the damage of spells and magic weapons.
+    pub fn new(amount: i32, damage_type: DamageType) -> Self {
+        Self {
+            amount,
+            damage_type,
+            magical: false,
+            critical: false,
+        }
+    }
+    pub fn magical(mut self, magical: bool) -> Self {
+        self.magical = magical;
+        self
+    }
+    pub fn is_magical(&self) -> bool {
+        self.magical
+    }
+    pub fn critical(mut self, critical: bool) -> Self {
+        self.critical = critical;
+        self
+    }
+    pub fn is_critical(&self) -> bool {
+        self.critical
+    }
+    pub fn amount(&self) -> i32 {
+        self.amount
+    }
+    pub fn damage_type(&self) -> DamageType {
+        self.damage_type
+    }
+}
+impl fmt::Display for Damage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.amount, self.damage_type)
+    }
+}
@@ -1,14 +1,69 @@
+use crate::distribution::Distribution;
+use crate::formula::{Formula, FormulaErrorKind, FormulaParseError};
 use rand::Rng;
 use regex::Regex;
 use serde::{Deserialize, Serialize};
-use std::num::ParseIntError;
-use std::ops::{Add, AddAssign, Div, DivAssign, Mul, MulAssign, Sub, SubAssign};
+use std::fmt;
+use std::ops::{Add, Mul, MulAssign, Sub};
 use std::str::FromStr;
 
-#[derive(Default, Clone, Copy, Debug, Serialize, Deserialize)]
+/// Regex of a group of dice with its roll modifiers: the dice count, the face count and the
+/// modifiers (`kh3`, `r<=2`, `min2`, `!`, ...) are captured.
+pub(crate) const DICE_PATTERN: &str =
+    r"([0-9]*)d([0-9]+)((?:k[hl]?[0-9]+|d[hl][0-9]+|ro?(?:<=|>=|<|>)?[0-9]+|min[0-9]+|!)*)";
+/// Maximum number of extra dice rolled by a single exploding die.
+const EXPLOSION_LIMIT: usize = 10;
+
+/// Which dice of a group are summed once they are all rolled.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Keep {
+    Highest(i32),
+    Lowest(i32),
+    DropHighest(i32),
+    DropLowest(i32),
+}
+impl Keep {
+    /// Return if the highest dice are kept and how many of them.
+    fn kept(&self, dice_count: i32) -> (bool, i32) {
+        match *self {
+            Keep::Highest(n) => (true, n.clamp(0, dice_count)),
+            Keep::Lowest(n) => (false, n.clamp(0, dice_count)),
+            Keep::DropHighest(n) => (false, (dice_count - n).clamp(0, dice_count)),
+            Keep::DropLowest(n) => (true, (dice_count - n).clamp(0, dice_count)),
+        }
+    }
+}
+
+/// Reroll a die when its result is between `min` and `max` (inclusive).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Reroll {
+    min: i32,
+    max: i32,
+    #[serde(default)]
+    once: bool,
+}
+impl Reroll {
+    fn matches(&self, value: i32) -> bool {
+        self.min <= value && value <= self.max
+    }
+}
+
+/// A group of identical dice, like `3d6` or `4d6kh3`.
+/// A negative `dice_count` means the group is subtracted (`-1d4`).
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Dice {
     dice_count: i32,
     face_count: i32,
+    #[serde(default)]
+    keep: Option<Keep>,
+    #[serde(default)]
+    reroll: Option<Reroll>,
+    //Any die result below the minimum counts as the minimum
+    #[serde(default)]
+    minimum: i32,
+    //A die showing its highest face is rolled again and added
+    #[serde(default)]
+    explode: bool,
 }
 
 impl Dice {
@@ -16,23 +71,153 @@ impl Dice {
         Self {
             dice_count,
             face_count,
+            ..Default::default()
         }
     }
-    pub fn roll(&self) -> i32 {
-        let mut rng = rand::thread_rng();
-        let mut sum = 0;
-        for _ in 0..self.dice_count {
-            sum += rng.gen_range(1..=self.face_count);
+    pub fn roll<R: Rng + ?Sized>(&self, rng: &mut R) -> i32 {
+        let mut rolls: Vec<i32> = (0..self.dice_count.abs())
+            .map(|_| self.roll_die(rng))
+            .collect();
+        let sum: i32 = if let Some(keep) = self.keep {
+            let (highest, count) = keep.kept(rolls.len() as i32);
+            rolls.sort_unstable();
+            if highest {
+                rolls.iter().rev().take(count as usize).sum()
+            } else {
+                rolls.iter().take(count as usize).sum()
+            }
+        } else {
+            rolls.iter().sum()
+        };
+        sum * self.dice_count.signum()
+    }
+    fn roll_face<R: Rng + ?Sized>(&self, rng: &mut R) -> i32 {
+        let mut value = rng.gen_range(1..=self.face_count);
+        if let Some(reroll) = self.reroll {
+            if reroll.once {
+                if reroll.matches(value) {
+                    value = rng.gen_range(1..=self.face_count);
+                }
+            } else {
+                while reroll.matches(value) {
+                    value = rng.gen_range(1..=self.face_count);
+                }
+            }
         }
-        sum
+        value.max(self.minimum)
+    }
+    fn roll_die<R: Rng + ?Sized>(&self, rng: &mut R) -> i32 {
+        let mut total = 0;
+        for depth in 0..=EXPLOSION_LIMIT {
+            let value = self.roll_face(rng);
+            total += value;
+            if !self.explode || value != self.face_count || depth == EXPLOSION_LIMIT {
+                break;
+            }
+        }
+        total
+    }
+    /// Probability of each result of a single die, indexed by the result.
+    fn die_probabilities(&self) -> Vec<f64> {
+        let faces = self.face_count as usize;
+        let uniform = 1.0 / faces as f64;
+        let mut face = vec![0.0; faces + 1];
+        for (value, p) in face.iter_mut().enumerate().skip(1) {
+            *p = match self.reroll {
+                None => uniform,
+                Some(reroll) => {
+                    let rerolled = (1..=faces as i32).filter(|v| reroll.matches(*v)).count();
+                    let rerolled = rerolled as f64 * uniform;
+                    let kept = if reroll.matches(value as i32) {
+                        0.0
+                    } else {
+                        uniform
+                    };
+                    if reroll.once {
+                        kept + rerolled * uniform
+                    } else {
+                        kept / (1.0 - rerolled)
+                    }
+                }
+            };
+        }
+        let minimum = (self.minimum.max(1) as usize).min(faces);
+        let below: f64 = face[..minimum].iter().sum();
+        face[..minimum].iter_mut().for_each(|p| *p = 0.0);
+        face[minimum] += below;
+        if !self.explode {
+            return face;
+        }
+        //Each explosion shifts the result by the face count
+        let again = face[faces];
+        let mut total = vec![0.0; faces * (EXPLOSION_LIMIT + 1) + 1];
+        let mut chain = 1.0;
+        for depth in 0..=EXPLOSION_LIMIT {
+            for (value, p) in face.iter().enumerate() {
+                if value != faces || depth == EXPLOSION_LIMIT {
+                    total[depth * faces + value] += chain * p;
+                }
+            }
+            chain *= again;
+        }
+        total
+    }
+    /// Probability of each sum of the group (ignoring its sign), indexed by the sum.
+    fn sum_probabilities(&self) -> Vec<f64> {
+        let die = self.die_probabilities();
+        let dice_count = self.dice_count.abs();
+        let (highest, kept) = match self.keep {
+            Some(keep) => keep.kept(dice_count),
+            None => (true, dice_count),
+        };
+        let values: Vec<usize> = if highest {
+            (0..die.len()).rev().collect()
+        } else {
+            (0..die.len()).collect()
+        };
+        //dp[assigned][sum]: probability that the `assigned` best dice are known and their
+        //kept part sums to `sum`. Values are visited from the best to the worst.
+        let dice_count = dice_count as usize;
+        let kept = kept as usize;
+        let max_sum = kept * (die.len() - 1);
+        let mut dp = vec![vec![0.0; max_sum + 1]; dice_count + 1];
+        dp[0][0] = 1.0;
+        for value in values {
+            let p = die[value];
+            if p == 0.0 {
+                continue;
+            }
+            let mut next = vec![vec![0.0; max_sum + 1]; dice_count + 1];
+            for assigned in 0..=dice_count {
+                for sum in 0..=max_sum {
+                    let current = dp[assigned][sum];
+                    if current == 0.0 {
+                        continue;
+                    }
+                    let mut weight = current;
+                    for count in 0..=(dice_count - assigned) {
+                        if count > 0 {
+                            //binomial(remaining, count) * p^count
+                            weight *= p * (dice_count - assigned - count + 1) as f64 / count as f64;
+                        }
+                        let added = kept.saturating_sub(assigned).min(count) * value;
+                        next[assigned + count][sum + added] += weight;
+                    }
+                }
+            }
+            dp = next;
+        }
+        dp.pop().unwrap()
     }
     pub fn is_dice(s: &str) -> bool {
         let reg = Regex::new(r"[0-9]+d([1-9][0-9]*)").unwrap();
         reg.captures(s).is_some()
     }
+    /// Add `amount` dice to the group, or remove them if negative, down to no dice.
+    /// A subtracted group stays subtracted.
     pub fn add_dice(&mut self, amount: i32) {
-        self.dice_count += amount;
-        self.dice_count = self.dice_count.max(0);
+        let count = (self.dice_count.abs() + amount).max(0);
+        self.dice_count = if self.is_negative() { -count } else { count };
     }
     pub fn dice_count(&self) -> i32 {
         self.dice_count
@@ -40,80 +225,210 @@ impl Dice {
     pub fn face_count(&self) -> i32 {
         self.face_count
     }
-    pub fn average_roll(&self) -> f32 {
-        (self.dice_count * self.face_count) as f32 / 2.0
+    /// The same group of dice with another amount of dice.
+    pub fn with_count(self, dice_count: i32) -> Self {
+        Self { dice_count, ..self }
     }
-}
-
-impl From<&str> for Dice {
-    fn from(item: &str) -> Self {
-        let reg = Regex::new(r"([0-9]+)d([1-9][0-9]*)").unwrap();
-        if let Some(capture) = reg.captures(item) {
-            let dice_count = capture.get(1).unwrap().as_str().parse::<i32>().unwrap();
-            let face_count = capture.get(2).unwrap().as_str().parse::<i32>().unwrap();
-            Self {
-                face_count,
-                dice_count,
-            }
+    pub fn keep(&self) -> Option<Keep> {
+        self.keep
+    }
+    pub fn is_negative(&self) -> bool {
+        self.dice_count < 0
+    }
+    /// Two groups of the same kind can be merged into a single group.
+    /// Groups keeping only some of their dice are never merged, 4d6kh3 twice isn't 8d6kh3.
+    pub fn same_kind(&self, other: &Self) -> bool {
+        self.keep.is_none()
+            && other.keep.is_none()
+            && self.face_count == other.face_count
+            && self.reroll == other.reroll
+            && self.minimum == other.minimum
+            && self.explode == other.explode
+            && self.is_negative() == other.is_negative()
+    }
+    /// Exact distribution of the group, modifiers included.
+    pub fn distribution(&self) -> Distribution {
+        let distribution = Distribution::new(0, self.sum_probabilities());
+        if self.is_negative() {
+            distribution.negate()
         } else {
-            panic!("The Dice string has the wrong format : {item}");
+            distribution
         }
     }
-}
-impl From<&String> for Dice {
-    fn from(item: &String) -> Self {
-        Self::from(item.as_str())
-    }
-}
-impl FromStr for Dice {
-    type Err = ParseIntError;
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        Ok(Self::from(s))
+    /// Expected result of the group. Only the kept and exploding dice need the whole distribution,
+    /// the others are the mean of a single die times their count.
+    pub fn average_roll(&self) -> f32 {
+        if self.keep.is_some() || self.explode {
+            return self.distribution().mean() as f32;
+        }
+        let die = if self.reroll.is_none() && self.minimum <= 1 {
+            (self.face_count + 1) as f64 / 2.0
+        } else {
+            self.die_probabilities()
+                .iter()
+                .enumerate()
+                .map(|(value, p)| value as f64 * p)
+                .sum()
+        };
+        (self.dice_count as f64 * die) as f32
     }
-}
-impl Add for Dice {
-    type Output = Self;
-    fn add(self, other: Self) -> Self {
-        if other.dice_count > 0 {
-            assert_eq!(self.face_count, other.face_count);
-            Self {
-                face_count: self.face_count,
-                dice_count: self.dice_count + other.dice_count,
+    /// Build a group of dice from the captures of `DICE_PATTERN`.
+    /// On error, return the offset of the faulty part from the start of `dice_count`.
+    pub(crate) fn from_parts(
+        dice_count: &str,
+        face_count: &str,
+        modifiers: &str,
+    ) -> Result<Self, (usize, FormulaErrorKind)> {
+        let parse = |number: &str, offset: usize| {
+            number
+                .parse::<i32>()
+                .map_err(|_| (offset, FormulaErrorKind::NumberTooLarge))
+        };
+        let faces_offset = dice_count.len() + 1;
+        let modifiers_offset = faces_offset + face_count.len();
+        let dice_count = match dice_count {
+            "" => 1,
+            count => parse(count, 0)?,
+        };
+        let face_count = parse(face_count, faces_offset)?;
+        if face_count == 0 {
+            return Err((faces_offset, FormulaErrorKind::NoFaces));
+        }
+        let mut dice = Self::new(dice_count, face_count);
+        let reg = Regex::new(
+            r"k([hl]?)([0-9]+)|d([hl])([0-9]+)|r(o?)(<=|>=|<|>|)([0-9]+)|min([0-9]+)|(!)",
+        )
+        .unwrap();
+        let mut has_minimum = false;
+        for capture in reg.captures_iter(modifiers) {
+            let offset = modifiers_offset + capture.get(0).unwrap().start();
+            // A second modifier of a kind would overwrite the first one
+            let duplicate = if capture.get(2).is_some() || capture.get(4).is_some() {
+                dice.keep.is_some()
+            } else if capture.get(7).is_some() {
+                dice.reroll.is_some()
+            } else if capture.get(8).is_some() {
+                has_minimum
+            } else {
+                dice.explode
+            };
+            if duplicate {
+                return Err((offset, FormulaErrorKind::DuplicateModifier));
+            }
+            if let Some(count) = capture.get(2) {
+                let count = parse(count.as_str(), modifiers_offset + count.start())?;
+                dice.keep = Some(match &capture[1] {
+                    "l" => Keep::Lowest(count),
+                    _ => Keep::Highest(count),
+                });
+            } else if let Some(count) = capture.get(4) {
+                let count = parse(count.as_str(), modifiers_offset + count.start())?;
+                dice.keep = Some(match &capture[3] {
+                    "l" => Keep::DropLowest(count),
+                    _ => Keep::DropHighest(count),
+                });
+            } else if let Some(value) = capture.get(7) {
+                let value = parse(value.as_str(), modifiers_offset + value.start())?;
+                let (min, max) = match &capture[6] {
+                    "<=" => (1, value),
+                    "<" => (1, value - 1),
+                    ">=" => (value, face_count),
+                    ">" => (value + 1, face_count),
+                    _ => (value, value),
+                };
+                let once = !capture[5].is_empty();
+                if !once && min <= 1 && max >= face_count {
+                    return Err((offset, FormulaErrorKind::RerollEverything));
+                }
+                dice.reroll = Some(Reroll { min, max, once });
+            } else if let Some(minimum) = capture.get(8) {
+                let minimum = parse(minimum.as_str(), modifiers_offset + minimum.start())?;
+                if minimum > face_count {
+                    return Err((offset, FormulaErrorKind::MinimumAboveFaces));
+                }
+                dice.minimum = minimum;
+                has_minimum = true;
+            } else {
+                if face_count == 1 {
+                    return Err((offset, FormulaErrorKind::SingleFaceExplosion));
+                }
+                dice.explode = true;
             }
-        } else {
-            self
         }
+        Ok(dice)
     }
 }
-impl AddAssign for Dice {
-    fn add_assign(&mut self, other: Self) {
-        if other.dice_count > 0 {
-            assert_eq!(self.face_count, other.face_count);
-            self.dice_count += other.dice_count;
+
+impl fmt::Display for Dice {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}d{}", self.dice_count, self.face_count)?;
+        if self.explode {
+            write!(f, "!")?;
+        }
+        if let Some(reroll) = self.reroll {
+            let once = if reroll.once { "o" } else { "" };
+            if reroll.min == reroll.max {
+                write!(f, "r{once}{}", reroll.min)?;
+            } else if reroll.min <= 1 {
+                write!(f, "r{once}<={}", reroll.max)?;
+            } else {
+                write!(f, "r{once}>={}", reroll.min)?;
+            }
+        }
+        if self.minimum > 1 {
+            write!(f, "min{}", self.minimum)?;
+        }
+        match self.keep {
+            Some(Keep::Highest(n)) => write!(f, "kh{n}"),
+            Some(Keep::Lowest(n)) => write!(f, "kl{n}"),
+            Some(Keep::DropHighest(n)) => write!(f, "dh{n}"),
+            Some(Keep::DropLowest(n)) => write!(f, "dl{n}"),
+            None => Ok(()),
         }
     }
 }
-impl Sub for Dice {
-    type Output = Self;
-    fn sub(self, other: Self) -> Self {
-        if other.dice_count > 0 {
-            assert_eq!(self.face_count, other.face_count);
-            Self {
-                face_count: self.face_count,
-                dice_count: (self.dice_count - other.dice_count).max(0),
-            }
+
+impl FromStr for Dice {
+    type Err = FormulaParseError;
+    fn from_str(item: &str) -> Result<Self, Self::Err> {
+        let reg = Regex::new(&format!(r"^\s*([+\-]?)\s*{DICE_PATTERN}\s*")).unwrap();
+        let error = |position, kind| FormulaParseError::new(item, position, kind);
+        if item.trim().is_empty() {
+            return Err(error(0, FormulaErrorKind::Empty));
+        }
+        let Some(capture) = reg.captures(item) else {
+            let position = item.len() - item.trim_start_matches([' ', '+', '-']).len();
+            return Err(error(position, FormulaErrorKind::ExpectedDice));
+        };
+        let end = capture.get(0).unwrap().end();
+        if let Some(character) = item[end..].chars().next() {
+            return Err(error(end, FormulaErrorKind::UnexpectedCharacter(character)));
+        }
+        let start = capture.get(2).unwrap().start();
+        let dice = Self::from_parts(&capture[2], &capture[3], &capture[4])
+            .map_err(|(offset, kind)| error(start + offset, kind))?;
+        if &capture[1] == "-" {
+            Ok(dice.with_count(-dice.dice_count))
         } else {
-            self
+            Ok(dice)
         }
     }
 }
-impl SubAssign for Dice {
-    fn sub_assign(&mut self, other: Self) {
-        if other.dice_count > 0 {
-            assert_eq!(self.face_count, other.face_count);
-            self.dice_count -= other.dice_count;
-            self.dice_count = self.dice_count.max(0);
-        }
+//Mixing groups of dice gives a formula, 1d8+1d6 can't be a single group
+impl Add for Dice {
+    type Output = Formula;
+    fn add(self, other: Self) -> Formula {
+        let mut formula = Formula::from(self);
+        formula.push_dice(other);
+        formula
+    }
+}
+impl Sub for Dice {
+    type Output = Formula;
+    fn sub(self, other: Self) -> Formula {
+        let mut formula = Formula::from(self);
+        formula.push_dice(other.with_count(-other.dice_count));
+        formula
     }
 }
 impl Mul<i32> for Dice {
@@ -121,8 +436,8 @@ impl Mul<i32> for Dice {
     fn mul(self, other: i32) -> Self {
         assert!(other >= 0);
         Self {
-            face_count: self.face_count,
             dice_count: self.dice_count * other,
+            ..self
         }
     }
 }
@@ -132,3 +447,88 @@ impl MulAssign<i32> for Dice {
         self.dice_count *= other;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    fn dice(s: &str) -> Dice {
+        s.parse().unwrap()
+    }
+    fn assert_close(a: f32, b: f32) {
+        assert!((a - b).abs() < 1e-3, "{a} != {b}");
+    }
+    #[test]
+    fn from() {
+        assert_eq!(dice("4d6kh3").keep(), Some(Keep::Highest(3)));
+        assert_eq!(dice("2d20kl1").keep(), Some(Keep::Lowest(1)));
+        assert_eq!(dice("4d6dl1").keep(), Some(Keep::DropLowest(1)));
+        assert_eq!(dice("4d6k3"), dice("4d6kh3"));
+        assert_eq!(dice("2d6r<=2").to_string(), "2d6r<=2");
+        assert_eq!(dice("2d6r<3").to_string(), "2d6r<=2");
+        assert_eq!(dice("1d20ro1").to_string(), "1d20ro1");
+        assert_eq!(dice("8d6min2").to_string(), "8d6min2");
+        assert_eq!(dice("-1d4").dice_count(), -1);
+        assert_eq!(dice("d6!").to_string(), "1d6!");
+    }
+    #[test]
+    fn from_str_errors() {
+        let error = "1d6r<=6".parse::<Dice>().unwrap_err();
+        assert_eq!(error.kind(), FormulaErrorKind::RerollEverything);
+        assert_eq!(error.position(), 3);
+        let error = "2d6x".parse::<Dice>().unwrap_err();
+        assert_eq!(error.kind(), FormulaErrorKind::UnexpectedCharacter('x'));
+        assert_eq!(error.position(), 3);
+        let error = " 3".parse::<Dice>().unwrap_err();
+        assert_eq!(error.kind(), FormulaErrorKind::ExpectedDice);
+        assert_eq!(error.position(), 1);
+        let error = "1d0".parse::<Dice>().unwrap_err();
+        assert_eq!(error.kind(), FormulaErrorKind::NoFaces);
+        assert_eq!(error.position(), 2);
+        let error = "1d6r1r2".parse::<Dice>().unwrap_err();
+        assert_eq!(error.kind(), FormulaErrorKind::DuplicateModifier);
+        assert_eq!(error.position(), 5);
+        let error = "4d6kh3dl1".parse::<Dice>().unwrap_err();
+        assert_eq!(error.kind(), FormulaErrorKind::DuplicateModifier);
+        assert_eq!(error.position(), 6);
+        assert!("2d10r1min2!".parse::<Dice>().is_ok());
+    }
+    #[test]
+    fn average_roll() {
+        assert_close(dice("1d6").average_roll(), 3.5);
+        assert_close(dice("-1d4").average_roll(), -2.5);
+        assert_close(dice("2d20kh1").average_roll(), 13.825);
+        assert_close(dice("2d20kl1").average_roll(), 7.175);
+        assert_close(dice("4d6kh3").average_roll(), 12.2446);
+        assert_close(dice("4d6dl1").average_roll(), 12.2446);
+        assert_close(dice("2d6ro<=2").average_roll(), 25.0 / 3.0);
+        assert_close(dice("1d6r1").average_roll(), 4.0);
+        assert_close(dice("1d6min2").average_roll(), 22.0 / 6.0);
+        assert_close(dice("1d6!").average_roll(), 4.2);
+        //Without the distribution, the same mean as with it
+        for group in ["3d8", "-2d4", "2d6r<=2", "4d6ro1", "3d6min3", "2d10r1min2"] {
+            let group = dice(group);
+            assert_close(group.average_roll(), group.distribution().mean() as f32);
+        }
+    }
+    #[test]
+    fn mixed_dice() {
+        assert_eq!((dice("1d8") + dice("1d6")).to_string(), "1d8+1d6");
+        assert_eq!((dice("1d8") + dice("2d8")).to_string(), "3d8");
+        //The kept dice stay apart from the others
+        let formula = dice("4d6kh3") + dice("1d6");
+        assert_eq!(formula.dice(), [dice("4d6kh3"), dice("1d6")]);
+        assert_close(formula.average_roll(), 15.7446);
+        assert_close((dice("1d8") - dice("1d4")).average_roll(), 2.0);
+    }
+    #[test]
+    fn roll() {
+        let mut rng = rand::thread_rng();
+        for _ in 0..200 {
+            let kept = dice("4d6kh3").roll(&mut rng);
+            assert!((3..=18).contains(&kept));
+            assert!(dice("3d6min2").roll(&mut rng) >= 6);
+            assert!(dice("2d6r<=2").roll(&mut rng) >= 6);
+            assert!((-4..=-1).contains(&dice("-1d4").roll(&mut rng)));
+        }
+    }
+}
@@ -0,0 +1,182 @@
+/// Exact probability distribution of an integer roll.
+/// `probabilities[i]` is the probability of rolling `offset + i`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Distribution {
+    offset: i32,
+    probabilities: Vec<f64>,
+}
+
+impl Distribution {
+    /// The distribution of a roll that always gives `value`.
+    pub fn constant(value: i32) -> Self {
+        Self {
+            offset: value,
+            probabilities: vec![1.0],
+        }
+    }
+    pub fn new(offset: i32, probabilities: Vec<f64>) -> Self {
+        let mut distribution = Self {
+            offset,
+            probabilities,
+        };
+        distribution.trim();
+        distribution
+    }
+    //Remove the impossible values on both ends so min and max are meaningful
+    fn trim(&mut self) {
+        let first = self.probabilities.iter().position(|p| *p > 0.0);
+        let last = self.probabilities.iter().rposition(|p| *p > 0.0);
+        match (first, last) {
+            (Some(first), Some(last)) => {
+                self.probabilities.truncate(last + 1);
+                self.probabilities.drain(..first);
+                self.offset += first as i32;
+            }
+            _ => *self = Self::constant(self.offset),
+        }
+    }
+    /// Distribution of the sum of two independent rolls.
+    pub fn convolve(&self, other: &Self) -> Self {
+        let mut probabilities = vec![0.0; self.probabilities.len() + other.probabilities.len() - 1];
+        for (i, p) in self.probabilities.iter().enumerate() {
+            for (j, q) in other.probabilities.iter().enumerate() {
+                probabilities[i + j] += p * q;
+            }
+        }
+        Self::new(self.offset + other.offset, probabilities)
+    }
+    pub fn shift(&self, amount: i32) -> Self {
+        Self {
+            offset: self.offset + amount,
+            probabilities: self.probabilities.clone(),
+        }
+    }
+    pub fn negate(&self) -> Self {
+        Self {
+            offset: -self.max(),
+            probabilities: self.probabilities.iter().rev().copied().collect(),
+        }
+    }
+    /// Distribution of the roll divided by two and rounded down, like half damage.
+    pub fn halved(&self) -> Self {
+        let offset = self.min().div_euclid(2);
+        let mut probabilities = vec![0.0; (self.max().div_euclid(2) - offset + 1) as usize];
+        for (value, p) in self.iter() {
+            probabilities[(value.div_euclid(2) - offset) as usize] += p;
+        }
+        Self::new(offset, probabilities)
+    }
+    /// Iterate over the possible values and their probability.
+    pub fn iter(&self) -> impl Iterator<Item = (i32, f64)> + '_ {
+        self.probabilities
+            .iter()
+            .enumerate()
+            .map(|(i, p)| (self.offset + i as i32, *p))
+    }
+    /// Probability of rolling exactly `value`.
+    pub fn pmf(&self, value: i32) -> f64 {
+        let index = value - self.offset;
+        if index < 0 {
+            0.0
+        } else {
+            self.probabilities
+                .get(index as usize)
+                .copied()
+                .unwrap_or(0.0)
+        }
+    }
+    /// Probability of rolling `value` or less.
+    pub fn cdf(&self, value: i32) -> f64 {
+        self.iter()
+            .take_while(|(v, _)| *v <= value)
+            .map(|(_, p)| p)
+            .sum::<f64>()
+            .min(1.0)
+    }
+    /// Probability of rolling `value` or more, like the chance to deal at least the remaining hp.
+    pub fn at_least(&self, value: i32) -> f64 {
+        (1.0 - self.cdf(value - 1)).max(0.0)
+    }
+    pub fn min(&self) -> i32 {
+        self.offset
+    }
+    pub fn max(&self) -> i32 {
+        self.offset + self.probabilities.len() as i32 - 1
+    }
+    pub fn mean(&self) -> f64 {
+        self.iter().map(|(v, p)| v as f64 * p).sum()
+    }
+    pub fn variance(&self) -> f64 {
+        let mean = self.mean();
+        self.iter()
+            .map(|(v, p)| (v as f64 - mean).powi(2) * p)
+            .sum()
+    }
+    pub fn standard_deviation(&self) -> f64 {
+        self.variance().sqrt()
+    }
+    /// Smallest value whose cumulative probability reaches `percentile` (between 0 and 1).
+    pub fn percentile(&self, percentile: f64) -> i32 {
+        let mut cumulative = 0.0;
+        for (value, p) in self.iter() {
+            cumulative += p;
+            //Tolerate the rounding errors of the convolutions
+            if cumulative >= percentile - 1e-9 {
+                return value;
+            }
+        }
+        self.max()
+    }
+    pub fn median(&self) -> i32 {
+        self.percentile(0.5)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::formula::Formula;
+    fn formula(s: &str) -> Formula {
+        s.parse().unwrap()
+    }
+    fn assert_close(a: f64, b: f64) {
+        assert!((a - b).abs() < 1e-9, "{a} != {b}");
+    }
+    #[test]
+    fn single_die() {
+        let d = formula("1d6").distribution();
+        assert_eq!(d.min(), 1);
+        assert_eq!(d.max(), 6);
+        assert_close(d.mean(), 3.5);
+        assert_close(d.variance(), 35.0 / 12.0);
+        assert_close(d.pmf(4), 1.0 / 6.0);
+        assert_close(d.pmf(7), 0.0);
+        assert_close(d.cdf(2), 2.0 / 6.0);
+        assert_close(d.at_least(5), 2.0 / 6.0);
+    }
+    #[test]
+    fn convolution() {
+        let d = formula("2d6").distribution();
+        assert_close(d.pmf(7), 6.0 / 36.0);
+        assert_close(d.pmf(2), 1.0 / 36.0);
+        assert_eq!(d.median(), 7);
+        assert_eq!(d.percentile(0.0), 2);
+        assert_eq!(d.percentile(1.0), 12);
+        let d = formula("2d6-1d4+3").distribution();
+        assert_eq!(d.min(), 1);
+        assert_eq!(d.max(), 14);
+        assert_close(d.mean(), 7.5);
+        assert_close(d.iter().map(|(_, p)| p).sum(), 1.0);
+        let d = formula("5").distribution();
+        assert_eq!((d.min(), d.max()), (5, 5));
+        assert_close(d.variance(), 0.0);
+    }
+    #[test]
+    fn fireball_kills_goblin() {
+        //8d6 against 7 hp, and the goblin saving for half damage
+        let fireball = formula("8d6").distribution();
+        assert_close(fireball.at_least(7), 1.0);
+        assert!(fireball.halved().at_least(14) < 0.6);
+        assert_eq!(fireball.halved().min(), 4);
+        assert_close(formula("1d4").distribution().halved().mean(), 1.0);
+    }
+}
@@ -1,13 +1,57 @@
-use crate::event::{EventType, HandleEvent};
+use serde::{Deserialize, Serialize};
 
+use crate::event::{Event, EventType, HandleEvent, Timing};
+
+/// The creature whose events count down a duration.
+#[derive(Default, Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Creature {
+    #[default]
+    Target,
+    Source,
+}
+
+#[derive(Default, Copy, Clone, Debug, Serialize, Deserialize)]
 pub struct Duration {
     event_type : EventType,
     count : i32,
+    #[serde(default)]
+    creature : Creature,
+    //Only used by the Turn durations
+    #[serde(default)]
+    timing : Timing,
+}
+
+impl Duration {
+    pub fn new(event_type: EventType, count: i32) -> Self {
+        Self {
+            event_type,
+            count,
+            ..Default::default()
+        }
+    }
+    /// Count the events of `creature`, at `timing` for the turns, instead of the ones of the target.
+    pub fn turn_of(mut self, creature: Creature, timing: Timing) -> Self {
+        self.creature = creature;
+        self.timing = timing;
+        self
+    }
+    pub fn event_type(&self) -> EventType {
+        self.event_type
+    }
+    pub fn creature(&self) -> Creature {
+        self.creature
+    }
+    pub fn timing(&self) -> Timing {
+        self.timing
+    }
+    pub fn is_over(&self) -> bool {
+        self.count <= 0
+    }
 }
 
 impl HandleEvent for Duration {
-    fn handle(&mut self, event_type : EventType) {
-        if event_type == self.event_type {
+    fn handle(&mut self, event : &Event) {
+        if event.event_type == self.event_type && (event.event_type != EventType::Turn || event.timing == self.timing) {
             self.count -= 1;
         }
     }
@@ -1,8 +1,12 @@
 use std::collections::HashMap;
 
-use crate::duration::Duration;
-use crate::event::{EventType, HandleEvent};
+use crate::condition::Condition;
+use crate::duration::{Creature, Duration};
+use crate::event::{Event, EventType, HandleEvent, Timing};
+use crate::formula::Formula;
 use crate::modifier::{ModifierType, Modifier};
+use crate::spell::SpellInfo;
+use crate::template::{EffectTemplate, RepeatSave};
 
 pub struct Effect {
     name: String,
@@ -11,24 +15,117 @@ pub struct Effect {
     id: i32,
     modifiers : HashMap<ModifierType, Modifier>,
     durations: Vec<Duration>,
+    conditions: Vec<Condition>,
+    repeat_save: Option<RepeatSave>,
+    concentration: bool,
+    //Creature whose turn was in progress when the effect started, that turn doesn't count
+    started_during: Option<i32>,
+    //Spell that created the effect, it can be dispelled
+    spell: Option<SpellInfo>,
+    temp_hp: Option<Formula>,
 }
 
 impl Effect {
+    pub fn new(id: i32, template: &EffectTemplate, source_id: i32, target_id: i32) -> Self {
+        let modifiers = template
+            .modifiers
+            .iter()
+            .fold(HashMap::new(), |mut modifiers, modifier| {
+                *modifiers
+                    .entry(modifier.mod_type())
+                    .or_insert_with(|| Modifier::new(modifier.mod_type())) += modifier;
+                modifiers
+            });
+        Self {
+            name: template.name.clone(),
+            target_ids: vec![target_id],
+            source_id,
+            id,
+            modifiers,
+            durations: template.durations.clone(),
+            conditions: template.conditions.clone(),
+            repeat_save: template.repeat_save,
+            concentration: template.concentration,
+            started_during: None,
+            spell: None,
+            temp_hp: template.temp_hp.clone(),
+        }
+    }
+    /// Mark the effect as created by `spell`.
+    pub fn spell(mut self, spell: Option<SpellInfo>) -> Self {
+        self.spell = spell;
+        self
+    }
+    pub fn get_spell(&self) -> Option<&SpellInfo> {
+        self.spell.as_ref()
+    }
+    /// Start the effect during the turn of the creature `id`.
+    pub fn started_during(mut self, id: Option<i32>) -> Self {
+        self.started_during = id;
+        self
+    }
     pub fn get_modifier(&self, mod_type : ModifierType) -> Option<&Modifier> {
         self.modifiers.get(&mod_type)
     }
+    pub fn name(&self) -> &str {
+        &self.name
+    }
     pub fn id(&self) -> i32 {
         self.id
     }
+    pub fn source_id(&self) -> i32 {
+        self.source_id
+    }
+    pub fn target_ids(&self) -> &[i32] {
+        &self.target_ids
+    }
+    pub fn conditions(&self) -> &[Condition] {
+        &self.conditions
+    }
+    /// Temporary hit points given to the targets while the effect lasts.
+    pub fn temp_hp(&self) -> Option<&Formula> {
+        self.temp_hp.as_ref()
+    }
+    pub fn repeat_save(&self) -> Option<RepeatSave> {
+        self.repeat_save
+    }
+    /// Whether the effect ends with the concentration of its source.
+    pub fn concentration(&self) -> bool {
+        self.concentration
+    }
     pub fn targets(&self, id : i32) -> bool {
-        todo!("Effect::targets");
+        self.target_ids.contains(&id)
     }
     pub fn affects(&self, mod_type : ModifierType) -> bool {
-        todo!("Effect::affects");
+        self.modifiers.contains_key(&mod_type)
+    }
+    /// How much the effect is worth to the AI, see `Condition::value`.
+    pub fn value(&self) -> f32 {
+        self.conditions.iter().map(Condition::value).sum::<f32>() + self.modifiers.len() as f32
+    }
+    /// Whether all the durations ran out. Effects without duration last until they are removed.
+    pub fn is_over(&self) -> bool {
+        !self.durations.is_empty() && self.durations.iter().all(Duration::is_over)
     }
 }
 impl HandleEvent for Effect {
-    fn handle(&mut self, event_type: EventType) {
-        self.durations.iter_mut().for_each(|d| d.handle(event_type));
+    fn handle(&mut self, event: &Event) {
+        //The turn in progress when the effect started doesn't count
+        if event.event_type == EventType::Turn
+            && event.timing == Timing::End
+            && self.started_during.take() == Some(event.source_id)
+        {
+            return;
+        }
+        let subject = event.subject();
+        let (source_id, targeted) = (self.source_id, self.targets(subject));
+        self.durations
+            .iter_mut()
+            .filter(|d| match d.creature() {
+                _ if event.event_type == EventType::Round => true,
+                Creature::Target => targeted,
+                Creature::Source => source_id == subject,
+            })
+            .for_each(|d| d.handle(event));
     }
 }
@@ -1,5 +1,8 @@
 use serde::{Deserialize, Serialize};
 
+use crate::damage::DamageType;
+use crate::fight::Fight;
+
 #[derive(Default, Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum EventType {
     #[default]
@@ -8,8 +11,77 @@ pub enum EventType {
     DealDamage,
     ReceiveDamage,
     NewEffect,
+    Action,
+    Heal,
+    Death,
+}
+
+/// When a turn event happens, like "until the end of its next turn".
+#[derive(Default, Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Timing {
+    Start,
+    #[default]
+    End,
+}
+
+/// Something that happened in a fight. `source_id` is the creature acting (the one whose turn it is
+/// for a Turn event) and `target_id` the one it acts upon, they are the same when there is no target.
+#[derive(Default, Clone, Debug, PartialEq)]
+pub struct Event {
+    pub event_type: EventType,
+    pub source_id: i32,
+    pub target_id: i32,
+    pub amount: i32,
+    pub damage_type: Option<DamageType>,
+    pub action: Option<String>,
+    pub timing: Timing,
+}
+impl Event {
+    pub fn new(event_type: EventType, source_id: i32, target_id: i32) -> Self {
+        Self {
+            event_type,
+            source_id,
+            target_id,
+            ..Default::default()
+        }
+    }
+    /// A new round, it has no creature so its ids are -1.
+    pub fn round() -> Self {
+        Self::new(EventType::Round, -1, -1)
+    }
+    pub fn turn(id: i32, timing: Timing) -> Self {
+        Self {
+            timing,
+            ..Self::new(EventType::Turn, id, id)
+        }
+    }
+    pub fn amount(mut self, amount: i32) -> Self {
+        self.amount = amount;
+        self
+    }
+    pub fn damage_type(mut self, damage_type: DamageType) -> Self {
+        self.damage_type = Some(damage_type);
+        self
+    }
+    pub fn action(mut self, action: Option<String>) -> Self {
+        self.action = action;
+        self
+    }
+    /// The creature the event is about: the one receiving damage, healing or dying, otherwise the one acting.
+    pub fn subject(&self) -> i32 {
+        match self.event_type {
+            EventType::ReceiveDamage | EventType::Heal | EventType::Death => self.target_id,
+            _ => self.source_id,
+        }
+    }
 }
 
 pub trait HandleEvent {
-    fn handle(&mut self, event_type : EventType);
+    fn handle(&mut self, event : &Event);
+}
+
+/// Mechanics reacting to what happens in a fight, like Hellish Rebuke or a regeneration.
+/// The events they emit while being notified are sent after the current one.
+pub trait Observer {
+    fn notify(&mut self, event: &Event, fight: &mut Fight);
 }
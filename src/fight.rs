@@ -1,20 +1,91 @@
 use crate::{
-    action::*,
+    ability::Ability,
+    action::ActionStruct,
+    condition::Condition,
+    damage::Damage,
+    duration::{Creature, Duration},
     effect::Effect,
+    float::F32,
+    formula::Formula,
+    event::{Event, EventType, HandleEvent, Observer, Timing},
+    template::EffectTemplate,
     modifier::{Modifier, ModifierType},
-    monster::Monster,
+    monster::{Monster, Stakes},
+    spell::SpellInfo,
 };
 use core::cell::RefCell;
-use std::collections::HashSet;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::mem;
+
+/// Place of a creature in the initiative order.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Initiative {
+    id: i32,
+    roll: i32,
+    dexterity: i32,
+    //Random draw breaking the ties that remain after dexterity
+    tiebreak: u32,
+}
+impl Initiative {
+    pub fn id(&self) -> i32 {
+        self.id
+    }
+    pub fn roll(&self) -> i32 {
+        self.roll
+    }
+    //Ordering of the turns, the first to play is the smallest
+    fn turn_order(&self, other: &Self) -> Ordering {
+        other
+            .roll
+            .cmp(&self.roll)
+            .then(other.dexterity.cmp(&self.dexterity))
+            .then(other.tiebreak.cmp(&self.tiebreak))
+    }
+}
+
+//Lair actions of a creature, played on initiative count 20
+struct Lair {
+    id: i32,
+    owner_id: i32,
+    //The last one played, it can't be played two rounds in a row
+    last: Option<String>,
+}
 
 pub struct Fight {
     next_id: i32,
     entities: Vec<RefCell<Monster>>,
     effects: Vec<Effect>,
+    initiatives: Vec<Initiative>,
+    //Index in initiatives of the creature playing
+    turn: usize,
+    //Ids of the creatures that died, in the order they died
+    deaths: Vec<i32>,
+    observers: Vec<Box<dyn Observer>>,
+    //Events waiting to be sent, the ones emitted while sending another wait for it
+    events: VecDeque<Event>,
+    dispatching: bool,
+    //Name of the action being played, given to the events it causes
+    action: Option<String>,
+    //Spell being cast, or whose effect the creatures save against
+    spell: Option<SpellInfo>,
+    //Spell each caster concentrates on
+    concentrations: HashMap<i32, String>,
+    lairs: Vec<Lair>,
+    rng: StdRng,
 }
 impl Fight {
-    pub fn new(mut entities: Vec<Monster>) -> Self {
-        Self {
+    pub fn new(entities: Vec<Monster>) -> Self {
+        Self::with_rng(entities, StdRng::from_entropy())
+    }
+    /// Create a fight whose dice are drawn from `seed`, the same seed and entities give the same fight.
+    pub fn with_seed(entities: Vec<Monster>, seed: u64) -> Self {
+        Self::with_rng(entities, StdRng::seed_from_u64(seed))
+    }
+    fn with_rng(mut entities: Vec<Monster>, rng: StdRng) -> Self {
+        let mut fight = Self {
             next_id: entities.len() as i32,
             entities: entities
                 .iter_mut()
@@ -25,56 +96,233 @@ impl Fight {
                 })
                 .collect(),
             effects: Vec::new(),
+            initiatives: Vec::new(),
+            turn: 0,
+            deaths: Vec::new(),
+            observers: Vec::new(),
+            events: VecDeque::new(),
+            dispatching: false,
+            action: None,
+            spell: None,
+            concentrations: HashMap::new(),
+            lairs: Vec::new(),
+            rng,
+        };
+        let mut initiatives = fight
+            .entities
+            .iter()
+            .map(|e| fight_initiative(&e.borrow(), &mut fight.rng))
+            .collect::<Vec<_>>();
+        initiatives.sort_by(Initiative::turn_order);
+        fight.initiatives = initiatives;
+        for id in 0..fight.entities.len() as i32 {
+            fight.add_lair(id);
+        }
+        fight
+    }
+    /// Add a creature to a fight in progress and return its id.
+    /// It rolls initiative and plays after the creatures with the same initiative and dexterity.
+    pub fn add_entity(&mut self, mut entity: Monster) -> i32 {
+        let id = self.next_id();
+        entity.set_id(id);
+        let mut initiative = fight_initiative(&entity, &mut self.rng);
+        //Lose the remaining ties against the creatures already there
+        initiative.tiebreak = 0;
+        self.insert_initiative(initiative);
+        self.entities.push(RefCell::new(entity));
+        self.add_lair(id);
+        id
+    }
+    fn insert_initiative(&mut self, initiative: Initiative) {
+        let index = self
+            .initiatives
+            .partition_point(|i| i.turn_order(&initiative) != Ordering::Greater);
+        self.initiatives.insert(index, initiative);
+        //Keep the creature currently playing, it plays this round if it comes after it
+        if index < self.turn {
+            self.turn += 1;
         }
     }
+    //The lair of a creature with lair actions plays on initiative count 20, losing the ties
+    fn add_lair(&mut self, owner_id: i32) {
+        if self.get_entity(owner_id).is_none_or(|e| e.borrow().lair_actions().is_empty()) {
+            return;
+        }
+        let id = self.next_id();
+        self.insert_initiative(Initiative {
+            id,
+            roll: 20,
+            dexterity: i32::MIN,
+            tiebreak: 0,
+        });
+        self.lairs.push(Lair {
+            id,
+            owner_id,
+            last: None,
+        });
+    }
+    /// The creatures in the order they play each round.
+    pub fn initiative_order(&self) -> &[Initiative] {
+        &self.initiatives
+    }
+    pub fn rng(&mut self) -> &mut StdRng {
+        &mut self.rng
+    }
+    /// Spell being cast, if the action played is one.
+    pub fn spell(&self) -> Option<&SpellInfo> {
+        self.spell.as_ref()
+    }
     pub fn advance_round(&mut self) {
         eprintln!("==== New Round ====");
-        //The loop for each entity's turn
-        for idx in 0..self.entities.len() {
-            let current_id;
-            {
-                //NOTE this thing *must* be *mut*
-                let e = self.entities.get(idx).unwrap();
-                current_id = e.borrow().id();
-                e.borrow_mut().new_turn();
+        self.emit(Event::round());
+        //The loop for each entity's turn, in initiative order
+        self.turn = 0;
+        while self.turn < self.initiatives.len() {
+            let current_id = self.initiatives[self.turn].id();
+            if let Some(index) = self.lairs.iter().position(|lair| lair.id == current_id) {
+                self.lair_action(index);
+                self.turn += 1;
+                continue;
+            }
+            //NOTE this thing *must* be *mut*
+            let died = {
+                let entity = self.entities.iter().find(|e| e.borrow().id() == current_id);
+                let mut e = entity.unwrap().borrow_mut();
+                e.new_turn();
+                e.roll_recharges(&mut self.rng);
+                //The dying roll their death save at the start of their turn
+                let dying = e.is_dying();
+                e.roll_death_save(&mut self.rng);
+                dying && e.is_dead()
+            };
+            if died {
+                self.creature_died(current_id);
             }
+            self.emit(Event::turn(current_id, Timing::Start));
             loop {
                 let mut action = None;
                 {
-                    let mut e = self.entities.get(idx).unwrap().borrow_mut();
-                    let is_alive = e.is_alive();
-                    if is_alive {
+                    let mut e = self.get_entity(current_id).unwrap().borrow_mut();
+                    //Unconscious and incapacitated creatures lose their turn
+                    if e.is_conscious() && !e.is_incapacitated() {
                         action = e.take_action(self);
                         if action.is_some() {
-                            eprintln!("Playing {} {idx} (hp: {})", e.name(), e.hp());
+                            eprintln!("Playing {} {current_id} (hp: {})", e.name(), e.hp());
                         }
                     }
                 }
 
-                if let Some(mut action) = action {
-                    action.ready_for_apply();
-                    for act in action.get_components() {
-                        let e = self.entities.get(idx).unwrap().borrow();
-                        let targets = e.get_targets(self, act);
-                        drop(e);
-                        //TODO what about the action that affect the fight (turn into a wolf, add/another monster or effect)
-                        for id in targets {
-                            act.apply(current_id, id as i32, self);
-                        }
-
-                        // self.entities
-                        //     .iter_mut()
-                        //     .enumerate()
-                        //     .filter(|(i, _)| targets.contains(i))
-                        //     .for_each(|(_, m)| act.apply(m.get_mut(), self));
-                    }
+                if let Some((name, action)) = action {
+                    self.play_action(current_id, name, action);
                 } else {
                     break;
                 }
             }
+            self.end_of_turn(current_id);
+            self.legendary_actions(current_id);
+            self.turn += 1;
+        }
+    }
+    //Apply the components of the action played by the creature `id` to their targets
+    fn play_action(&mut self, id: i32, name: String, mut action: ActionStruct) {
+        if action.spell_level().is_some_and(|level| self.counter_spell(id, level)) {
+            eprintln!("{name} is countered");
+            return;
+        }
+        if action.is_concentration() {
+            self.concentrate(id, &name);
+        }
+        self.action = Some(name);
+        self.spell = action.spell().cloned();
+        self.emit(Event::new(EventType::Action, id, id).action(self.action.clone()));
+        action.ready_for_apply(&mut self.rng);
+        for act in action.get_components() {
+            let e = self.get_entity(id).unwrap().borrow();
+            let targets = e.get_targets(self, act);
+            drop(e);
+            //TODO what about the action that affect the fight (turn into a wolf, add/another monster or effect)
+            for target_id in targets {
+                act.apply(id, target_id, self);
+            }
+
+            // self.entities
+            //     .iter_mut()
+            //     .enumerate()
+            //     .filter(|(i, _)| targets.contains(i))
+            //     .for_each(|(_, m)| act.apply(m.get_mut(), self));
+        }
+        self.action = None;
+        self.spell = None;
+    }
+    //The lair plays the best of its actions, except the one played the previous round
+    fn lair_action(&mut self, index: usize) {
+        let owner_id = self.lairs[index].owner_id;
+        let action = {
+            let Some(owner) = self.get_entity(owner_id) else {
+                return;
+            };
+            let owner = owner.borrow();
+            if !owner.is_alive() {
+                return;
+            }
+            let last = self.lairs[index].last.as_ref();
+            owner
+                .lair_actions()
+                .iter()
+                .filter(|(name, _)| Some(*name) != last)
+                .map(|(name, action)| (action.average_dammage() + action.average_effect(), name, action))
+                .filter(|(value, _, _)| *value > 0.0)
+                //Break ties on the name so the choice doesn't depend on the HashMap order
+                .max_by(|(a_value, a, _), (b_value, b, _)| {
+                    F32(*a_value).cmp(&F32(*b_value)).then_with(|| b.cmp(a))
+                })
+                .map(|(_, name, action)| (name.clone(), action.clone()))
+        };
+        self.lairs[index].last = action.as_ref().map(|(name, _)| name.clone());
+        if let Some((name, action)) = action {
+            eprintln!("Lair action {name} (initiative 20)");
+            self.play_action(owner_id, name, action);
+        }
+    }
+    //At the end of the turn of `current_id`, the other creatures may play one legendary action each
+    fn legendary_actions(&mut self, current_id: i32) {
+        let ids = self
+            .initiatives
+            .iter()
+            .map(Initiative::id)
+            .filter(|id| *id != current_id)
+            .collect::<Vec<_>>();
+        for id in ids {
+            let action = {
+                //The lairs have no legendary actions
+                let Some(e) = self.get_entity(id) else {
+                    continue;
+                };
+                let mut e = e.borrow_mut();
+                if !e.is_conscious() || e.is_incapacitated() {
+                    continue;
+                }
+                let action = e.take_legendary_action(self);
+                if action.is_some() {
+                    eprintln!("Playing legendary action of {} {id} (hp: {})", e.name(), e.hp());
+                }
+                action
+            };
+            if let Some((name, action)) = action {
+                self.play_action(id, name, action);
+            }
         }
     }
     pub fn play(&mut self) -> Option<u8> {
+        for initiative in &self.initiatives {
+            if let Some(lair) = self.lairs.iter().find(|lair| lair.id == initiative.id()) {
+                let owner = self.get_entity(lair.owner_id).unwrap().borrow();
+                eprintln!("Initiative {} : lair of {} ({})", initiative.roll(), owner.name(), owner.id());
+                continue;
+            }
+            let e = self.get_entity(initiative.id()).unwrap().borrow();
+            eprintln!("Initiative {} : {} ({})", initiative.roll(), e.name(), e.id());
+        }
         let mut teams = HashSet::new();
         self.team_alive(&mut teams);
 
@@ -86,7 +334,7 @@ impl Fight {
         }
 
         //Return the winner team
-        if teams.len() == 0 {
+        if teams.is_empty() {
             None
         } else {
             Some(*teams.iter().last().unwrap())
@@ -96,41 +344,1279 @@ impl Fight {
         for e in self.entities.iter() {
             let e = e.borrow();
             eprintln!("{} : {}", e.name(), e.hp());
-            if e.is_alive() {
+            //A team with only stable unconscious members is out of the fight
+            if e.is_conscious() || e.is_dying() {
                 teams.insert(e.team());
             }
         }
     }
-    pub fn decrease_hp(&mut self, id: i32, amount: i32) {
-        todo!("Fight::inflict_damage");
+    pub fn get_entity(&self, id: i32) -> Option<&RefCell<Monster>> {
+        self.entities.iter().find(|e| e.borrow().id() == id)
     }
-    pub fn get_hp(&mut self, id: i32) -> i32 {
-        todo!("Fight::get_hp");
+    /// Inflict `damage` to the creature `id`, after its immunities, resistances and vulnerabilities,
+    /// and return the hp it lost.
+    /// A dead creature loses nothing, and `None` is returned if no creature has this id.
+    pub fn decrease_hp(&mut self, id: i32, damage: Damage) -> Option<i32> {
+        let mut entity = self.get_entity(id)?.borrow_mut();
+        if entity.is_dead() {
+            return Some(0);
+        }
+        let hp = entity.hp();
+        let amount = entity.damage_taken(damage);
+        entity.decrease_hp(amount.max(0), damage.is_critical());
+        let lost = hp - entity.hp();
+        let died = entity.is_dead();
+        let conscious = entity.is_conscious();
+        drop(entity);
+        if died {
+            self.creature_died(id);
+        } else if amount > 0 && self.concentrations.contains_key(&id) {
+            //Keeping the concentration takes a Constitution save against half the damage, at least 10
+            if !conscious || !self.roll_concentration(id, (amount / 2).max(10)) {
+                self.break_concentration(id);
+            }
+        }
+        Some(lost)
+    }
+    /// Deal `damage` from the creature `source_id` to the creature `target_id` and return the hp it lost.
+    pub fn inflict_damage(&mut self, source_id: i32, target_id: i32, damage: Damage) -> Option<i32> {
+        let lost = self.decrease_hp(target_id, damage)?;
+        let name = |id| self.get_entity(id).map(|e| e.borrow().name().to_string());
+        eprintln!(
+            "{} deals {damage} damage to {} (loses {lost}) -> hp target : {}",
+            name(source_id).unwrap_or_default(),
+            name(target_id).unwrap_or_default(),
+            self.get_hp(target_id).unwrap_or(0)
+        );
+        for event_type in [EventType::DealDamage, EventType::ReceiveDamage] {
+            let event = Event::new(event_type, source_id, target_id)
+                .amount(lost)
+                .damage_type(damage.damage_type())
+                .action(self.action.clone());
+            self.emit(event);
+        }
+        if lost > 0 {
+            self.retaliate(target_id, source_id);
+        }
+        Some(lost)
     }
-    pub fn get_ac(&mut self, id: i32) -> i32 {
-        todo!("Fight::get_ac");
+    /// Let the creature `id`, hit by an attack exceeding its AC by `margin`, react to turn it into a miss,
+    /// like with Shield, and return whether it did.
+    pub fn react_to_hit(&mut self, id: i32, margin: i32) -> bool {
+        let Some((reaction, bonus)) = self
+            .get_entity(id)
+            .and_then(|e| e.borrow_mut().armor_class_reaction(margin))
+        else {
+            return false;
+        };
+        //The attacks against it take the bonus as a penalty until the start of its next turn
+        let mut modifier = Modifier::new(ModifierType::Attacked);
+        modifier.add_formula(Formula::from(bonus), -1);
+        let template = EffectTemplate {
+            name: reaction.name().to_string(),
+            modifiers: vec![modifier],
+            durations: vec![Duration::new(EventType::Turn, 1).turn_of(Creature::Target, Timing::Start)],
+            ..Default::default()
+        };
+        //The effect comes from the reaction, not from the spell of the attacker
+        let spell = mem::replace(&mut self.spell, reaction.spell());
+        self.apply_effect(&template, id, id);
+        self.spell = spell;
+        true
+    }
+    //The creature `id` damaged by `source_id` may answer with its reaction, like Hellish Rebuke
+    fn retaliate(&mut self, id: i32, source_id: i32) {
+        let enemies = match (self.get_entity(id), self.get_entity(source_id)) {
+            (Some(e), Some(source)) => e.borrow().team() != source.borrow().team() && source.borrow().is_alive(),
+            _ => false,
+        };
+        if !enemies {
+            return;
+        }
+        let Some((reaction, mut components)) = self.get_entity(id).and_then(|e| e.borrow_mut().retaliation()) else {
+            return;
+        };
+        let action = self.action.replace(reaction.name().to_string());
+        let spell = mem::replace(&mut self.spell, reaction.spell());
+        for component in components.iter_mut() {
+            component.ready_for_apply(&mut self.rng);
+            component.apply(id, source_id, self);
+        }
+        self.action = action;
+        self.spell = spell;
+    }
+    //The enemies of the caster `id` may cancel its spell of `level` with their reaction, like Counterspell
+    fn counter_spell(&mut self, id: i32, level: i32) -> bool {
+        let Some(team) = self.get_entity(id).map(|e| e.borrow().team()) else {
+            return false;
+        };
+        let enemies = self
+            .initiatives
+            .iter()
+            .map(Initiative::id)
+            .filter(|other| self.get_entity(*other).is_some_and(|e| e.borrow().team() != team))
+            .collect::<Vec<_>>();
+        for enemy in enemies {
+            let counter = self.get_entity(enemy).unwrap().borrow_mut().counterspell(level);
+            if let Some((_, (counter_level, ability_modifier))) = counter {
+                if level <= counter_level {
+                    return true;
+                }
+                let check = self.rng.gen_range(1..=20) + ability_modifier;
+                eprintln!("Ability check {check} (DC {})", 10 + level);
+                if check >= 10 + level {
+                    return true;
+                }
+            }
+        }
+        false
+    }
+    /// Heal `amount` hp of the creature `target_id` from the creature `source_id` and return the hp it regained.
+    pub fn heal(&mut self, source_id: i32, target_id: i32, amount: i32) -> Option<i32> {
+        let regained = self.increase_hp(target_id, amount)?;
+        let name = |id| self.get_entity(id).map(|e| e.borrow().name().to_string());
+        eprintln!(
+            "{} heals {} of {amount} (regains {regained}) -> hp target : {}",
+            name(source_id).unwrap_or_default(),
+            name(target_id).unwrap_or_default(),
+            self.get_hp(target_id).unwrap_or(0)
+        );
+        let event = Event::new(EventType::Heal, source_id, target_id)
+            .amount(regained)
+            .action(self.action.clone());
+        self.emit(event);
+        Some(regained)
+    }
+    /// Give `amount` temporary hit points to the creature `id` and return the temporary hit points it has.
+    /// They don't stack with the ones it already has, the highest amount is kept.
+    pub fn add_temp_hp(&mut self, id: i32, amount: i32) -> Option<i32> {
+        let mut entity = self.get_entity(id)?.borrow_mut();
+        if entity.is_dead() {
+            return Some(0);
+        }
+        entity.add_temp_hp(amount);
+        eprintln!("{} has {} temporary hp", entity.name(), entity.temp_hp());
+        Some(entity.temp_hp())
+    }
+    /// Remove the temporary hit points of the creature `id`, when the effect granting them ends.
+    pub fn remove_temp_hp(&mut self, id: i32) -> Option<()> {
+        self.get_entity(id)?.borrow_mut().remove_temp_hp();
+        Some(())
+    }
+    /// Heal the creature `id` by `amount` (up to its max hp) and return the hp it regained.
+    /// The dead can't be healed, and `None` is returned if no creature has this id.
+    pub fn increase_hp(&mut self, id: i32, amount: i32) -> Option<i32> {
+        let mut entity = self.get_entity(id)?.borrow_mut();
+        if entity.is_dead() {
+            return Some(0);
+        }
+        let hp = entity.hp();
+        entity.increase_hp(amount.max(0));
+        Some(entity.hp() - hp)
+    }
+    fn creature_died(&mut self, id: i32) {
+        if let Some(entity) = self.get_entity(id) {
+            eprintln!("{} ({id}) dies", entity.borrow().name());
+        }
+        self.deaths.push(id);
+        self.break_concentration(id);
+        self.emit(Event::new(EventType::Death, id, id).action(self.action.clone()));
+        //The effects of a dead creature end with it
+        let ids = self
+            .effects
+            .iter()
+            .filter(|effect| effect.source_id() == id)
+            .map(Effect::id)
+            .collect::<Vec<_>>();
+        for id in ids {
+            self.remove_effect(id);
+        }
+    }
+    /// Ids of the creatures that died during the fight, in order.
+    pub fn deaths(&self) -> &[i32] {
+        &self.deaths
+    }
+    pub fn get_hp(&self, id: i32) -> Option<i32> {
+        self.get_entity(id).map(|e| e.borrow().hp())
+    }
+    pub fn get_ac(&self, id: i32) -> Option<i32> {
+        self.get_entity(id).map(|e| e.borrow().ac())
     }
     // pub fn add_condition(&mut self, source_id : i32, target_id : i32, ) NOTE must be breakable
+    /// Roll a saving throw of `ability` for the creature `id` and return whether it succeeds against `save_dc`.
+    /// A failed save may become a success with a legendary resistance, if what the creature loses,
+    /// the `stakes`, is worth it.
+    pub fn roll_save(&mut self, id: i32, ability: Ability, save_dc: i32, stakes: Stakes) -> bool {
+        let success = if self.fails_save(id, ability) {
+            eprintln!("Save automatically failed (DC {save_dc})");
+            false
+        } else {
+            let mut save_mod = self.get_modifier(id, ModifierType::Save(ability));
+            if self.spell.is_some() {
+                save_mod = save_mod + self.get_modifier(id, ModifierType::SpellSave);
+            }
+            let save = save_mod.roll(&mut self.rng).total();
+            eprintln!("Save {save} (DC {save_dc})");
+            save >= save_dc
+        };
+        success
+            || self
+                .get_entity(id)
+                .is_some_and(|entity| entity.borrow_mut().legendary_resistance(stakes))
+    }
+    /// Give the effect described by `template` from the creature `source_id` to `target_id`, and return its id.
+    pub fn apply_effect(&mut self, template: &EffectTemplate, source_id: i32, target_id: i32) -> Option<i32> {
+        self.get_entity(target_id)?;
+        let id = self.next_id();
+        self.add_effect(Effect::new(id, template, source_id, target_id).spell(self.spell.clone()));
+        let event = Event::new(EventType::NewEffect, source_id, target_id).action(self.action.clone());
+        self.emit(event);
+        Some(id)
+    }
+    /// Start an effect, giving its conditions and temporary hit points to its targets.
+    pub fn add_effect(&mut self, effect: Effect) {
+        let current_id = self.initiatives.get(self.turn).map(Initiative::id);
+        let effect = effect.started_during(current_id);
+        for id in effect.target_ids() {
+            for condition in effect.conditions() {
+                self.add_condition(*id, *condition);
+            }
+            if let Some(temp_hp) = effect.temp_hp() {
+                let amount = temp_hp.roll(&mut self.rng);
+                self.add_temp_hp(*id, amount);
+            }
+        }
+        self.effects.push(effect);
+    }
+    /// End the effect `id`, removing its conditions and temporary hit points from its targets.
+    pub fn remove_effect(&mut self, id: i32) -> Option<Effect> {
+        let index = self.effects.iter().position(|effect| effect.id() == id)?;
+        let effect = self.effects.remove(index);
+        eprintln!("{} ends", effect.name());
+        for id in effect.target_ids() {
+            for condition in effect.conditions() {
+                self.remove_condition(*id, *condition);
+            }
+            if effect.temp_hp().is_some() {
+                self.remove_temp_hp(*id);
+            }
+        }
+        Some(effect)
+    }
+    pub fn get_effects(&self) -> &[Effect] {
+        &self.effects
+    }
+    /// Notify `observer` of all the events of the fight.
+    pub fn subscribe(&mut self, observer: Box<dyn Observer>) {
+        self.observers.push(observer);
+    }
+    /// Send `event` to the effects, ending the ones that ran out, then to the observers.
+    pub fn emit(&mut self, event: Event) {
+        self.events.push_back(event);
+        if self.dispatching {
+            return;
+        }
+        self.dispatching = true;
+        while let Some(event) = self.events.pop_front() {
+            self.effects.iter_mut().for_each(|effect| effect.handle(&event));
+            self.remove_expired_effects();
+            //The observers may subscribe others while being notified
+            let mut observers = std::mem::take(&mut self.observers);
+            for observer in observers.iter_mut() {
+                observer.notify(&event, self);
+            }
+            observers.append(&mut self.observers);
+            self.observers = observers;
+        }
+        self.dispatching = false;
+    }
+    fn remove_expired_effects(&mut self) {
+        let ids = self
+            .effects
+            .iter()
+            .filter(|effect| effect.is_over())
+            .map(Effect::id)
+            .collect::<Vec<_>>();
+        for id in ids {
+            self.remove_effect(id);
+        }
+    }
+    //The creatures under an effect with a repeated save try to end it at the end of their turn
+    fn end_of_turn(&mut self, id: i32) {
+        if self.get_entity(id).is_some_and(|e| e.borrow().is_alive()) {
+            self.repeat_saves(id);
+        }
+        self.emit(Event::turn(id, Timing::End));
+    }
+    fn repeat_saves(&mut self, id: i32) {
+        let saves = self
+            .effects
+            .iter()
+            .filter(|effect| effect.targets(id))
+            .filter_map(|effect| {
+                let save = effect.repeat_save()?;
+                let stakes = Stakes {
+                    damage: 0.0,
+                    effect: effect.value(),
+                };
+                Some((effect.id(), save, stakes, effect.get_spell().cloned()))
+            })
+            .collect::<Vec<_>>();
+        for (effect_id, save, stakes, spell) in saves {
+            let spell = mem::replace(&mut self.spell, spell);
+            let success = self.roll_save(id, save.ability, save.save_dc, stakes);
+            self.spell = spell;
+            if success {
+                self.remove_effect(effect_id);
+            }
+        }
+    }
+    /// End the effects of the spells on the creature `id`, the spells of a higher level than
+    /// `level` end on an ability check against 10 + their level, like Dispel Magic.
+    pub fn dispel(&mut self, id: i32, level: i32, ability_modifier: i32) {
+        let spells = self
+            .effects
+            .iter()
+            .filter(|effect| effect.targets(id))
+            .filter_map(|effect| Some((effect.id(), effect.get_spell()?.level)))
+            .collect::<Vec<_>>();
+        for (effect_id, spell_level) in spells {
+            if spell_level > level {
+                let check = self.rng.gen_range(1..=20) + ability_modifier;
+                eprintln!("Ability check {check} (DC {})", 10 + spell_level);
+                if check < 10 + spell_level {
+                    continue;
+                }
+            }
+            self.remove_effect(effect_id);
+        }
+    }
+    /// Give `condition` to the creature `id`, once per source applying it.
+    /// An incapacitated creature loses its concentration.
+    pub fn add_condition(&mut self, id: i32, condition: Condition) -> Option<()> {
+        let mut entity = self.get_entity(id)?.borrow_mut();
+        eprintln!("{} is {condition}", entity.name());
+        entity.add_condition(condition);
+        let incapacitated = entity.is_incapacitated();
+        drop(entity);
+        if incapacitated {
+            self.break_concentration(id);
+        }
+        Some(())
+    }
+    /// Make the creature `id` concentrate on `spell`, ending its previous concentration.
+    pub fn concentrate(&mut self, id: i32, spell: &str) {
+        self.break_concentration(id);
+        if let Some(entity) = self.get_entity(id) {
+            eprintln!("{} concentrates on {spell}", entity.borrow().name());
+        }
+        self.concentrations.insert(id, spell.to_string());
+    }
+    /// Spell the creature `id` concentrates on.
+    pub fn concentration(&self, id: i32) -> Option<&str> {
+        self.concentrations.get(&id).map(String::as_str)
+    }
+    /// End the concentration of the creature `id` and the effects of its spell, return the spell.
+    pub fn break_concentration(&mut self, id: i32) -> Option<String> {
+        let spell = self.concentrations.remove(&id)?;
+        if let Some(entity) = self.get_entity(id) {
+            eprintln!("{} loses its concentration on {spell}", entity.borrow().name());
+        }
+        let ids = self
+            .effects
+            .iter()
+            .filter(|effect| effect.source_id() == id && effect.concentration())
+            .map(Effect::id)
+            .collect::<Vec<_>>();
+        for id in ids {
+            self.remove_effect(id);
+        }
+        Some(spell)
+    }
+    //Constitution save to keep the concentration, with the advantage of War Caster
+    fn roll_concentration(&mut self, id: i32, save_dc: i32) -> bool {
+        let save_mod = self.get_modifier(id, ModifierType::Save(Ability::Constitution))
+            + self.get_modifier(id, ModifierType::Concentration);
+        let save = save_mod.roll(&mut self.rng).total();
+        eprintln!("Concentration save {save} (DC {save_dc})");
+        save >= save_dc
+    }
+    /// Remove `condition` from the creature `id`, return false if it didn't have it.
+    pub fn remove_condition(&mut self, id: i32, condition: Condition) -> Option<bool> {
+        let mut entity = self.get_entity(id)?.borrow_mut();
+        let removed = entity.remove_condition(condition);
+        if removed {
+            eprintln!("{} is no longer {condition}", entity.name());
+        }
+        Some(removed)
+    }
+    /// Whether the creature `id` fails its `ability` saves without rolling, like a stunned one on Dexterity.
+    pub fn fails_save(&self, id: i32, ability: Ability) -> bool {
+        self.get_entity(id).is_some_and(|entity| {
+            entity
+                .borrow()
+                .conditions()
+                .iter()
+                .any(|condition| condition.fails_save(ability))
+        })
+    }
+    /// Whether the hits of an attack against the creature `id` are critical, like against a paralyzed one.
+    pub fn suffers_critical(&self, id: i32, ranged: bool) -> bool {
+        self.get_entity(id).is_some_and(|entity| {
+            entity
+                .borrow()
+                .conditions()
+                .iter()
+                .any(|condition| condition.suffers_critical(ranged))
+        })
+    }
+    /// Modifier of an attack of `source_id` against `target_id`: the attacker's `Attack` and the
+    /// target's `Attacked`, with their conditions.
+    pub fn get_attack_modifier(&self, source_id: i32, target_id: i32, ranged: bool) -> Modifier {
+        self.modifier_with_conditions(source_id, ModifierType::Attack, ranged)
+            + self.modifier_with_conditions(target_id, ModifierType::Attacked, ranged)
+    }
+    /// Modifier of the rolls of type `mod_type` of the creature `id`, attacks being melee ones.
     pub fn get_modifier(&self, id: i32, mod_type: ModifierType) -> Modifier {
-        let modifier = self.effects
+        self.modifier_with_conditions(id, mod_type, false)
+    }
+    fn modifier_with_conditions(&self, id: i32, mod_type: ModifierType, ranged: bool) -> Modifier {
+        let mut modifier = self.effects_modifier(id, mod_type);
+        if let Some(entity) = self.get_entity(id) {
+            let entity = entity.borrow();
+            for condition in entity.conditions() {
+                modifier += &condition.modifier(mod_type, ranged);
+            }
+            for feat in entity.modifiers(mod_type) {
+                modifier += feat;
+            }
+        }
+        modifier
+    }
+    fn effects_modifier(&self, id: i32, mod_type: ModifierType) -> Modifier {
+        //The same effect twice doesn't stack (two Bless), different effects do
+        let mut names = HashSet::new();
+        let mut modifier = self
+            .effects
             .iter()
-            .filter_map(|effect| { //Get effects that targets id and that affect my Modtype
-                if effect.targets(id) && effect.affects(mod_type) {
+            .filter_map(|effect| {
+                //Get effects that targets id and that affect my Modtype
+                if effect.targets(id) && effect.affects(mod_type) && names.insert(effect.name()) {
                     effect.get_modifier(mod_type)
                 } else {
                     None
                 }
             })
-            .fold(Modifier::default(), |mut acc, e| {
+            .fold(Modifier::new(mod_type), |mut acc, e| {
                 acc += e;
                 acc
             });
-
-
-        todo!("Fight::get_modifier | Add mod of the target for the saving throws.");
+        if let ModifierType::Save(ability) = mod_type {
+            if let Some(entity) = self.get_entity(id) {
+                modifier += &Modifier::from(entity.borrow().save_mod(ability));
+            }
+        }
         modifier
     }
     pub fn get_entities(&self) -> &Vec<RefCell<Monster>> {
         &self.entities
     }
+    /// Reserve a new id, for an entity or an effect, that is unique in this fight.
+    pub fn next_id(&mut self) -> i32 {
+        let id = self.next_id;
+        self.next_id += 1;
+        id
+    }
+}
+
+fn fight_initiative<R: Rng + ?Sized>(entity: &Monster, rng: &mut R) -> Initiative {
+    Initiative {
+        id: entity.id(),
+        roll: entity.roll_initiative(rng),
+        dexterity: entity.ability_mod(Ability::Dexterity),
+        tiebreak: rng.gen(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::damage::DamageType;
+    use crate::duration::{Creature, Duration};
+    use crate::resource::Resource;
+    use crate::spell::School;
+    use crate::template::{RepeatSave, TemplateBuilder};
+    use std::collections::HashMap;
+    use std::rc::Rc;
+    fn builder() -> TemplateBuilder {
+        let monsters = serde_json::from_str(include_str!("../gobelin.json")).unwrap();
+        let spells = serde_json::from_str(include_str!("../spells.json")).unwrap();
+        let mut builder = TemplateBuilder::new(monsters, spells);
+        builder.seed(7);
+        builder
+    }
+    fn goblins_against_kelani(seed: u64) -> Fight {
+        let mut builder = builder();
+        let monsters = vec![
+            builder.create("Gobelin").hp(7).team(0).build(),
+            builder.create("Gobelin").hp(8).team(0).build(),
+            builder.create("Kelani").team(1).build(),
+        ];
+        Fight::with_seed(monsters, seed)
+    }
+    #[test]
+    fn hp_and_ac() {
+        let mut fight = goblins_against_kelani(1);
+        assert_eq!(fight.get_hp(0), Some(7));
+        assert_eq!(fight.get_ac(0), Some(15));
+        assert_eq!(fight.get_hp(42), None);
+        assert_eq!(fight.decrease_hp(42, Damage::new(3, DamageType::Slashing)), None);
+        assert_eq!(fight.increase_hp(42, 3), None);
+
+        assert_eq!(fight.decrease_hp(0, Damage::new(3, DamageType::Slashing)), Some(3));
+        assert_eq!(fight.increase_hp(0, 10), Some(3));
+        assert_eq!(fight.get_hp(0), Some(7));
+        assert!(fight.deaths().is_empty());
+
+        //Overkill only removes the remaining hp
+        assert_eq!(fight.decrease_hp(0, Damage::new(10, DamageType::Slashing)), Some(7));
+        assert_eq!(fight.deaths(), &[0]);
+        //The dead are neither damaged nor healed, and only die once
+        assert_eq!(fight.decrease_hp(0, Damage::new(10, DamageType::Slashing)), Some(0));
+        assert_eq!(fight.increase_hp(0, 10), Some(0));
+        assert_eq!(fight.get_hp(0), Some(0));
+        assert_eq!(fight.deaths(), &[0]);
+    }
+    #[test]
+    fn same_seed_same_fight() {
+        let mut first = goblins_against_kelani(3);
+        let mut second = goblins_against_kelani(3);
+        let winner = first.play();
+        assert!(winner.is_some());
+        assert_eq!(winner, second.play());
+        assert_eq!(first.deaths(), second.deaths());
+    }
+    #[test]
+    fn initiative_order() {
+        let mut builder = builder();
+        let monsters = vec![
+            builder.create("Gobelin").initiative(5).build(),
+            builder.create("Kelani").initiative(12).build(),
+            //Same initiative as Kelani but a worse dexterity
+            builder.create("Black Bear").initiative(12).build(),
+            builder.create("Gobelin").build(),
+        ];
+        let mut fight = Fight::with_seed(monsters, 5);
+        let order = fight.initiative_order().iter().map(|i| i.id()).collect::<Vec<_>>();
+        let goblin = fight.initiative_order().iter().find(|i| i.id() == 3).unwrap();
+        assert!((3..=22).contains(&goblin.roll()));
+        let fixed = order.iter().filter(|id| **id != 3).copied().collect::<Vec<_>>();
+        assert_eq!(fixed, vec![1, 2, 0]);
+
+        //A newcomer plays after the creatures it ties with
+        let id = fight.add_entity(builder.create("Kelani").initiative(12).build());
+        assert_eq!(id, 4);
+        let order = fight.initiative_order().iter().map(|i| i.id()).collect::<Vec<_>>();
+        let position = |id| order.iter().position(|i| *i == id).unwrap();
+        assert_eq!(position(4), position(1) + 1);
+        assert_eq!(fight.get_hp(4), fight.get_hp(1));
+
+        //d20 + the dexterity modifier of 2, and the bonus of Alert
+        let mut alert = builder.get_monster_template("Gobelin");
+        alert.entity_stats.initiative = 5;
+        let alert = Monster::from_template(&builder, &alert, fight.rng());
+        let goblin = builder.create("Gobelin").build();
+        for _ in 0..100 {
+            assert!((3..=22).contains(&goblin.roll_initiative(fight.rng())));
+            assert!((8..=27).contains(&alert.roll_initiative(fight.rng())));
+        }
+    }
+    #[test]
+    fn resistances() {
+        let mut builder = builder();
+        let mut fight = Fight::with_seed(vec![builder.create("Skeleton").hp(30).build()], 1);
+        let damage = |amount, damage_type| Damage::new(amount, damage_type);
+        assert_eq!(fight.decrease_hp(0, damage(7, DamageType::Poison)), Some(0));
+        assert_eq!(fight.decrease_hp(0, damage(3, DamageType::Bludgeoning)), Some(6));
+        assert_eq!(fight.decrease_hp(0, damage(3, DamageType::Slashing)), Some(3));
+
+        //A werewolf-like creature only resists the weapons that aren't magical
+        let mut template = builder.get_monster_template("Skeleton");
+        template.name = "Wererat".to_string();
+        template.entity_stats.nonmagical_resistances = vec![DamageType::Slashing];
+        template.entity_stats.resistances = vec![DamageType::Bludgeoning];
+        let mut builder = TemplateBuilder::new(vec![template], HashMap::new());
+        let mut fight = Fight::with_seed(vec![builder.create("Wererat").hp(30).build()], 1);
+        assert_eq!(fight.decrease_hp(0, damage(7, DamageType::Slashing)), Some(3));
+        let magical = damage(7, DamageType::Slashing).magical(true);
+        assert_eq!(fight.decrease_hp(0, magical), Some(7));
+        //Resistance and vulnerability: halved first, then doubled
+        assert_eq!(fight.decrease_hp(0, damage(7, DamageType::Bludgeoning)), Some(6));
+    }
+    #[test]
+    fn healing() {
+        let builder = builder();
+        //Cure Wounds upcast to level 3 heals 3d8 + the spellcasting modifier
+        let cure_wounds = builder
+            .get_spell_template("Cure Wounds")
+            .spell_modifier(4)
+            .upcast(2)
+            .build();
+        assert_eq!(cure_wounds.healing_value(&[(100, 1.0)]), 17.5);
+        assert_eq!(cure_wounds.healing_value(&[(5, 2.0)]), 10.0);
+        assert_eq!(cure_wounds.healing_value(&[]), 0.0);
+
+        let mut builder = builder;
+        let monsters = vec![
+            builder.create("Kelani").team(0).build(),
+            builder.create("Kelani").team(0).build(),
+            builder.create("Gobelin").team(1).build(),
+        ];
+        let mut fight = Fight::with_seed(monsters, 4);
+        //Nobody is hurt, the cleric attacks
+        let (_, action) = fight.get_entity(0).unwrap().borrow_mut().take_action(&fight).unwrap();
+        assert_eq!(action.healing_value(&[(100, 1.0)]), 0.0);
+
+        //Its ally is about to fall, the cleric heals it instead
+        fight.decrease_hp(1, Damage::new(22, DamageType::Slashing));
+        fight.get_entity(0).unwrap().borrow_mut().new_turn();
+        let (_, mut action) = fight.get_entity(0).unwrap().borrow_mut().take_action(&fight).unwrap();
+        assert!(action.healing_value(&[(100, 1.0)]) > 0.0);
+        action.ready_for_apply(fight.rng());
+        let component = &action.get_components()[0];
+        let targets = fight.get_entity(0).unwrap().borrow().get_targets(&fight, component);
+        assert_eq!(targets, vec![1]);
+        component.apply(0, 1, &mut fight);
+        assert!(fight.get_hp(1).unwrap() > 5);
+    }
+    #[test]
+    fn temp_hp() {
+        let mut builder = builder();
+        let monsters = vec![builder.create("Gobelin").hp(7).temp_hp(5).build()];
+        let mut fight = Fight::with_seed(monsters, 1);
+        let slash = |amount| Damage::new(amount, DamageType::Slashing);
+        //Temporary hit points are lost first
+        assert_eq!(fight.decrease_hp(0, slash(3)), Some(0));
+        assert_eq!(fight.get_entity(0).unwrap().borrow().temp_hp(), 2);
+        assert_eq!(fight.decrease_hp(0, slash(4)), Some(2));
+        assert_eq!(fight.get_hp(0), Some(5));
+        //They don't stack, only the highest amount is kept
+        assert_eq!(fight.add_temp_hp(0, 6), Some(6));
+        assert_eq!(fight.add_temp_hp(0, 4), Some(6));
+        fight.remove_temp_hp(0);
+        assert_eq!(fight.get_entity(0).unwrap().borrow().temp_hp(), 0);
+        //The temporary hit points of an effect are lost when it ends
+        let armor_of_agathys = EffectTemplate {
+            name: "Armor of Agathys".to_string(),
+            temp_hp: Some(Formula::from(5)),
+            durations: vec![Duration::new(EventType::Round, 1)],
+            ..Default::default()
+        };
+        fight.apply_effect(&armor_of_agathys, 0, 0);
+        assert_eq!(fight.get_entity(0).unwrap().borrow().temp_hp(), 5);
+        fight.emit(Event::round());
+        assert!(fight.get_effects().is_empty());
+        assert_eq!(fight.get_entity(0).unwrap().borrow().temp_hp(), 0);
+
+        //False Life upcast to level 2 grants 1d4+9
+        let false_life = builder.get_spell_template("False Life").upcast(1).build();
+        assert_eq!(false_life.temp_hp_value(0), 11.5);
+        assert_eq!(false_life.temp_hp_value(20), 0.0);
+    }
+    #[test]
+    fn death_saves() {
+        let mut builder = builder();
+        let monsters = vec![
+            builder.create("Kelani").hp(10).team(0).build(),
+            builder.create("Kelani").hp(10).team(0).build(),
+            builder.create("Gobelin").hp(10).team(1).build(),
+        ];
+        let mut fight = Fight::with_seed(monsters, 1);
+        let slash = |amount| Damage::new(amount, DamageType::Slashing);
+        let kelani = |fight: &Fight, id| fight.get_entity(id).unwrap().borrow().clone();
+
+        //Player characters fall unconscious at 0 hp
+        fight.decrease_hp(0, slash(12));
+        assert!(kelani(&fight, 0).is_dying());
+        assert!(fight.deaths().is_empty());
+        //Damage while down fails a death save, two on a critical hit
+        fight.decrease_hp(0, slash(3));
+        assert_eq!(kelani(&fight, 0).death_saves().failures(), 1);
+        //Healing wakes them up
+        assert_eq!(fight.increase_hp(0, 4), Some(4));
+        assert!(kelani(&fight, 0).is_conscious());
+        assert_eq!(kelani(&fight, 0).death_saves().failures(), 0);
+        fight.decrease_hp(0, slash(4));
+        fight.decrease_hp(0, slash(3).critical(true));
+        fight.decrease_hp(0, slash(3));
+        assert!(kelani(&fight, 0).is_dead());
+        assert_eq!(fight.deaths(), &[0]);
+
+        //Massive damage kills outright
+        fight.decrease_hp(1, slash(20));
+        assert!(kelani(&fight, 1).is_dead());
+        assert_eq!(fight.deaths(), &[0, 1]);
+        //An NPC dies at 0 hp
+        fight.decrease_hp(2, slash(10));
+        assert!(kelani(&fight, 2).is_dead());
+    }
+    #[test]
+    fn downed_team() {
+        let mut builder = builder();
+        let monsters = vec![
+            builder.create("Kelani").hp(10).team(0).build(),
+            builder.create("Gobelin").team(1).build(),
+        ];
+        let mut fight = Fight::with_seed(monsters, 1);
+        fight.decrease_hp(0, Damage::new(10, DamageType::Slashing));
+        let mut teams = HashSet::new();
+        fight.team_alive(&mut teams);
+        assert_eq!(teams.len(), 2);
+        //Roll death saves until the character is dead, stable or up again
+        let mut rng = StdRng::seed_from_u64(3);
+        let mut kelani = fight.get_entity(0).unwrap().borrow().clone();
+        while kelani.is_dying() {
+            kelani.roll_death_save(&mut rng);
+        }
+        assert!(kelani.is_dead() || kelani.is_stable() || kelani.hp() == 1);
+        //The fight goes on while the character is dying, and ends once a team has no one
+        //up or dying: dead and stable creatures are out of the fight
+        let winner = fight.play();
+        for entity in fight.get_entities() {
+            let entity = entity.borrow();
+            let out = !entity.is_conscious() && !entity.is_dying();
+            assert_eq!(out, winner != Some(entity.team()));
+        }
+    }
+    #[test]
+    fn conditions() {
+        let mut builder = builder();
+        let monsters = vec![
+            builder.create("Gobelin").hp(10).team(0).build(),
+            builder.create("Gobelin").hp(10).team(1).build(),
+        ];
+        let mut fight = Fight::with_seed(monsters, 1);
+        fight.add_condition(1, Condition::Paralyzed);
+        assert!(fight.fails_save(1, Ability::Dexterity));
+        assert!(!fight.fails_save(1, Ability::Wisdom));
+        assert!(fight.suffers_critical(1, false));
+        assert!(!fight.suffers_critical(1, true));
+        assert!(fight.get_attack_modifier(0, 1, true).has_advantage());
+        //Prone cancels the advantage of a ranged attack
+        fight.add_condition(1, Condition::Prone);
+        assert!(!fight.get_attack_modifier(0, 1, true).has_advantage());
+        assert!(fight.get_attack_modifier(0, 1, false).has_advantage());
+        assert_eq!(fight.remove_condition(1, Condition::Prone), Some(true));
+        assert_eq!(fight.remove_condition(1, Condition::Prone), Some(false));
+
+        //Incapacitated creatures lose their turn
+        fight.add_condition(0, Condition::Stunned);
+        fight.advance_round();
+        assert_eq!(fight.get_hp(0), Some(10));
+        assert_eq!(fight.get_hp(1), Some(10));
+        fight.remove_condition(0, Condition::Stunned);
+        fight.advance_round();
+        assert_eq!(fight.get_hp(0), Some(10));
+        assert!(fight.get_hp(1) < Some(10));
+    }
+    #[test]
+    fn effects() {
+        let mut builder = builder();
+        let monsters = vec![
+            builder.create("Gobelin").hp(50).team(0).build(),
+            builder.create("Gobelin").hp(50).team(1).build(),
+        ];
+        let mut fight = Fight::with_seed(monsters, 1);
+        let mut hold = EffectTemplate {
+            name: "Hold Person".to_string(),
+            conditions: vec![Condition::Paralyzed],
+            repeat_save: Some(RepeatSave {
+                ability: Ability::Wisdom,
+                save_dc: 100,
+            }),
+            ..Default::default()
+        };
+        let id = fight.apply_effect(&hold, 0, 1).unwrap();
+        assert!(fight.get_entity(1).unwrap().borrow().is_incapacitated());
+        fight.advance_round();
+        assert_eq!(fight.get_hp(0), Some(50));
+        assert_eq!(fight.get_effects().len(), 1);
+
+        //A successful repeated save ends the effect and its conditions
+        fight.remove_effect(id);
+        hold.repeat_save = Some(RepeatSave {
+            ability: Ability::Wisdom,
+            save_dc: -100,
+        });
+        fight.apply_effect(&hold, 0, 1);
+        fight.advance_round();
+        assert!(fight.get_effects().is_empty());
+        assert!(!fight.get_entity(1).unwrap().borrow().is_incapacitated());
+    }
+    #[test]
+    fn effect_durations() {
+        let mut builder = builder();
+        let monsters = vec![
+            builder.create("Gobelin").hp(50).team(0).build(),
+            builder.create("Gobelin").hp(50).team(1).build(),
+        ];
+        let mut fight = Fight::with_seed(monsters, 1);
+        let mut mockery = EffectTemplate {
+            name: "Vicious Mockery".to_string(),
+            durations: vec![Duration::new(EventType::Turn, 1)],
+            ..Default::default()
+        };
+        //Until the end of the target's next turn
+        fight.apply_effect(&mockery, 0, 1);
+        fight.emit(Event::turn(0, Timing::End));
+        fight.emit(Event::turn(1, Timing::Start));
+        assert_eq!(fight.get_effects().len(), 1);
+        fight.emit(Event::turn(1, Timing::End));
+        assert!(fight.get_effects().is_empty());
+
+        //The turn in progress doesn't count
+        mockery.durations = vec![Duration::new(EventType::Turn, 1).turn_of(Creature::Source, Timing::End)];
+        fight.turn = fight.initiatives.iter().position(|i| i.id() == 0).unwrap();
+        fight.apply_effect(&mockery, 0, 1);
+        fight.emit(Event::turn(0, Timing::End));
+        assert_eq!(fight.get_effects().len(), 1);
+        fight.emit(Event::turn(0, Timing::Start));
+        fight.emit(Event::turn(0, Timing::End));
+        assert!(fight.get_effects().is_empty());
+
+        //Rounds
+        mockery.durations = vec![Duration::new(EventType::Round, 2)];
+        fight.apply_effect(&mockery, 0, 1);
+        fight.emit(Event::round());
+        assert_eq!(fight.get_effects().len(), 1);
+        fight.emit(Event::round());
+        assert!(fight.get_effects().is_empty());
+
+        //The effects end when their source dies
+        fight.apply_effect(&mockery, 0, 1);
+        fight.inflict_damage(1, 0, Damage::new(100, DamageType::Slashing));
+        assert!(fight.get_effects().is_empty());
+    }
+    //Deals 1 fire damage to the creatures hurting `id`, like a fire shield
+    struct FireShield {
+        id: i32,
+        events: Rc<RefCell<Vec<Event>>>,
+    }
+    impl Observer for FireShield {
+        fn notify(&mut self, event: &Event, fight: &mut Fight) {
+            self.events.borrow_mut().push(event.clone());
+            if event.event_type == EventType::ReceiveDamage && event.target_id == self.id {
+                fight.inflict_damage(self.id, event.source_id, Damage::new(1, DamageType::Fire));
+            }
+        }
+    }
+    #[test]
+    fn events() {
+        let mut builder = builder();
+        let monsters = vec![
+            builder.create("Gobelin").hp(50).team(0).build(),
+            builder.create("Gobelin").hp(50).team(1).build(),
+        ];
+        let mut fight = Fight::with_seed(monsters, 3);
+        let events = Rc::new(RefCell::new(Vec::new()));
+        fight.subscribe(Box::new(FireShield {
+            id: 1,
+            events: events.clone(),
+        }));
+        fight.inflict_damage(0, 1, Damage::new(5, DamageType::Slashing));
+        let types = events.borrow().iter().map(|e| e.event_type).collect::<Vec<_>>();
+        assert_eq!(
+            types,
+            [
+                EventType::DealDamage,
+                EventType::ReceiveDamage,
+                EventType::DealDamage,
+                EventType::ReceiveDamage
+            ]
+        );
+        assert_eq!(events.borrow()[1].amount, 5);
+        assert_eq!(events.borrow()[3].damage_type, Some(DamageType::Fire));
+        assert_eq!(fight.get_hp(0), Some(49));
+
+        events.borrow_mut().clear();
+        fight.advance_round();
+        let events = events.borrow();
+        assert_eq!(events[0], Event::round());
+        let action = events.iter().find(|e| e.event_type == EventType::Action).unwrap();
+        let damage = events.iter().find(|e| e.event_type == EventType::DealDamage);
+        assert!(action.action.is_some());
+        assert!(damage.is_none_or(|damage| damage.action == action.action));
+        assert_eq!(events.last(), Some(&Event::turn(fight.initiative_order()[1].id(), Timing::End)));
+    }
+    #[test]
+    fn concentration() {
+        let mut builder = builder();
+        let resilient = serde_json::from_str(
+            r#"{"mod_type": {"Save": "Constitution"}, "formulas": [{"fixed": 100}], "formula_multipliers": [1]}"#,
+        )
+        .unwrap();
+        let mut war_caster = Modifier::new(ModifierType::Concentration);
+        war_caster.add_advantage();
+        let monsters = vec![
+            builder.create("Gobelin").hp(200).team(0).modifier(resilient).build(),
+            builder.create("Gobelin").hp(200).team(1).modifier(war_caster).build(),
+        ];
+        assert!(builder.get_spell_template("Hold Person").build().is_concentration());
+        let mut fight = Fight::with_seed(monsters, 1);
+        assert!(fight.get_modifier(1, ModifierType::Concentration).has_advantage());
+        let hold = EffectTemplate {
+            name: "Hold Person".to_string(),
+            conditions: vec![Condition::Paralyzed],
+            concentration: true,
+            ..Default::default()
+        };
+        fight.concentrate(0, "Hold Person 2");
+        fight.apply_effect(&hold, 0, 1);
+        //Resilient keeps the concentration
+        fight.inflict_damage(1, 0, Damage::new(20, DamageType::Slashing));
+        assert_eq!(fight.concentration(0), Some("Hold Person 2"));
+        assert_eq!(fight.get_effects().len(), 1);
+
+        //A new concentration spell ends the previous one
+        fight.concentrate(0, "Hold Person 3");
+        assert!(fight.get_effects().is_empty());
+        assert!(!fight.get_entity(1).unwrap().borrow().is_incapacitated());
+
+        //DC 50, the concentration breaks
+        fight.concentrate(1, "Hold Person 2");
+        fight.apply_effect(&hold, 1, 0);
+        fight.inflict_damage(0, 1, Damage::new(100, DamageType::Slashing));
+        assert_eq!(fight.concentration(1), None);
+        assert!(fight.get_effects().is_empty());
+
+        //An incapacitated caster loses it too
+        fight.add_condition(0, Condition::Stunned);
+        assert_eq!(fight.concentration(0), None);
+    }
+    #[test]
+    fn recharge() {
+        let mut builder = builder();
+        let monsters = vec![
+            builder.create("Hell Hound").team(0).build(),
+            builder.create("Gobelin").hp(100).team(1).build(),
+        ];
+        let fight = Fight::with_seed(monsters, 2);
+        let breath = |fight: &Fight| {
+            let hound = fight.get_entity(0).unwrap().borrow();
+            hound.actions()["Fire Breath"].has_charges()
+        };
+        //The breath is the best action, the hound uses it first
+        let (name, _) = fight.get_entity(0).unwrap().borrow_mut().take_action(&fight).unwrap();
+        assert_eq!(name, "Fire Breath");
+        assert!(!breath(&fight));
+        fight.get_entity(0).unwrap().borrow_mut().new_turn();
+        let (name, _) = fight.get_entity(0).unwrap().borrow_mut().take_action(&fight).unwrap();
+        assert_eq!(name, "Bite");
+        //It comes back on a 5 or a 6
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut turns = 1;
+        while !breath(&fight) {
+            fight.get_entity(0).unwrap().borrow_mut().roll_recharges(&mut rng);
+            turns += 1;
+        }
+        assert!(turns > 1);
+        fight.get_entity(0).unwrap().borrow_mut().take_action(&fight);
+        fight.get_entity(0).unwrap().borrow_mut().long_rest();
+        assert!(breath(&fight));
+    }
+    //Records the names of the actions played
+    struct Actions(Rc<RefCell<Vec<(i32, String)>>>);
+    impl Observer for Actions {
+        fn notify(&mut self, event: &Event, _: &mut Fight) {
+            if event.event_type == EventType::Action {
+                let name = event.action.clone().unwrap_or_default();
+                self.0.borrow_mut().push((event.source_id, name));
+            }
+        }
+    }
+    #[test]
+    fn legendary() {
+        let mut builder = builder();
+        let monsters = vec![
+            builder.create("Adult Red Dragon").team(0).build(),
+            builder.create("Gobelin").hp(500).team(1).build(),
+            builder.create("Gobelin").hp(500).team(1).build(),
+            builder.create("Gobelin").hp(500).team(1).build(),
+        ];
+        let mut fight = Fight::with_seed(monsters, 5);
+        let actions = Rc::new(RefCell::new(Vec::new()));
+        fight.subscribe(Box::new(Actions(actions.clone())));
+        for _ in 0..3 {
+            fight.advance_round();
+            //3 points per round: a Wing Attack and a Tail Attack, or three Tail Attacks
+            let points = actions
+                .borrow()
+                .iter()
+                .map(|(_, name)| match name.as_str() {
+                    "Tail Attack" => 1,
+                    "Wing Attack" => 2,
+                    _ => 0,
+                })
+                .sum::<i32>();
+            assert_eq!(points, 3);
+            //Besides its action on its own turn
+            let dragon = actions.borrow().iter().position(|(_, name)| name != "Tail Attack" && name != "Wing Attack");
+            assert!(dragon.is_some());
+            actions.borrow_mut().clear();
+        }
+
+        //Legendary Resistance (3/Day), kept for what matters
+        let hold = Stakes {
+            damage: 0.0,
+            effect: Condition::Paralyzed.value(),
+        };
+        let scratch = Stakes {
+            damage: 5.0,
+            effect: 0.0,
+        };
+        assert!(!fight.roll_save(0, Ability::Wisdom, 100, scratch));
+        for _ in 0..3 {
+            assert!(fight.roll_save(0, Ability::Wisdom, 100, hold));
+        }
+        assert!(!fight.roll_save(0, Ability::Wisdom, 100, hold));
+    }
+    #[test]
+    fn lair() {
+        let mut builder = builder();
+        let monsters = vec![
+            builder.create("Adult Red Dragon").team(0).build(),
+            builder.create("Gobelin").hp(500).initiative(20).team(1).build(),
+        ];
+        let mut fight = Fight::with_seed(monsters, 5);
+        //The lair loses the ties on initiative 20
+        let order = fight.initiative_order().iter().map(Initiative::id).collect::<Vec<_>>();
+        assert_eq!(order.len(), 3);
+        let lair = order.iter().position(|id| *id == 2).unwrap();
+        assert!(order.iter().position(|id| *id == 1).unwrap() < lair);
+        assert!(fight.get_entity(2).is_none());
+
+        let actions = Rc::new(RefCell::new(Vec::new()));
+        fight.subscribe(Box::new(Actions(actions.clone())));
+        for _ in 0..4 {
+            fight.advance_round();
+        }
+        //Played by the dragon, never twice in a row
+        let lair_actions = actions
+            .borrow()
+            .iter()
+            .filter(|(_, name)| name == "Magma Eruption" || name == "Volcanic Gases")
+            .map(|(id, name)| (*id, name.clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(lair_actions.len(), 4);
+        assert!(lair_actions.iter().all(|(id, _)| *id == 0));
+        assert!(lair_actions.windows(2).all(|w| w[0].1 != w[1].1));
+    }
+    #[test]
+    fn reactions() {
+        let mut builder = builder();
+        let monsters = vec![
+            builder.create("Gobelin").hp(100).team(0).build(),
+            builder.create("Mage").team(1).build(),
+            builder.create("Tiefling Warlock").hp(100).team(1).build(),
+        ];
+        let mut fight = Fight::with_seed(monsters, 1);
+        //Shield only if it turns the hit into a miss
+        assert!(!fight.react_to_hit(1, 5));
+        let ice_knife = builder.get_spell_template("Ice Knife").build();
+        fight.spell = ice_knife.spell().cloned();
+        assert!(fight.react_to_hit(1, 4));
+        assert_eq!(fight.get_effects()[0].name(), "Shield");
+        //The effect comes from Shield, not from the spell it answers
+        let shield = fight.get_effects()[0].get_spell().unwrap();
+        assert_eq!((shield.name.as_str(), shield.school, shield.level), ("Shield", School::Abjuration, 1));
+        assert_eq!(fight.spell(), ice_knife.spell());
+        fight.spell = None;
+        assert!(!fight.react_to_hit(1, 0));
+        fight.get_entity(1).unwrap().borrow_mut().new_turn();
+        //Counterspell only on the leveled spells
+        assert!(!fight.counter_spell(0, 0));
+        assert!(fight.counter_spell(0, 2));
+        assert!(!fight.counter_spell(0, 2));
+
+        //Hellish Rebuke, once per round
+        fight.inflict_damage(0, 2, Damage::new(3, DamageType::Piercing));
+        assert!(fight.get_hp(0) < Some(100));
+        assert_eq!(fight.spell(), None);
+        let hp = fight.get_hp(0);
+        fight.inflict_damage(0, 2, Damage::new(3, DamageType::Piercing));
+        assert_eq!(fight.get_hp(0), hp);
+        //Not against its allies
+        fight.get_entity(2).unwrap().borrow_mut().new_turn();
+        fight.inflict_damage(1, 2, Damage::new(3, DamageType::Piercing));
+        assert_eq!(fight.get_hp(1), fight.get_entity(1).unwrap().borrow().max_hp().into());
+    }
+    #[test]
+    fn spells() {
+        let mut builder = builder();
+        let hold_person = builder.get_spell_template("Hold Person").upcast(1).build();
+        let spell = hold_person.spell().unwrap();
+        assert_eq!(spell.name, "Hold Person");
+        assert_eq!(spell.school, School::Enchantment);
+        assert_eq!(hold_person.spell_level(), Some(3));
+        assert!(spell.concentration);
+        let mut magic_resistance = Modifier::new(ModifierType::SpellSave);
+        magic_resistance.add_formula(Formula::from(100), 1);
+        let monsters = vec![
+            builder.create("Gobelin").hp(100).team(0).build(),
+            builder.create("Mage").team(1).build(),
+            builder.create("Gobelin").hp(100).team(1).modifier(magic_resistance).build(),
+            builder.create("Rakshasa").team(0).build(),
+        ];
+        let mut fight = Fight::with_seed(monsters, 1);
+        let mage = fight.get_entity(1).unwrap().borrow().clone();
+        assert!(mage.actions()["Dagger"].spell().is_none());
+        let dispel_magic = mage.actions()["Dispel Magic 3"].clone();
+        let ice_knife = mage.actions()["Ice Knife 1"].clone();
+
+        //Magic Resistance only against spells
+        let no_stakes = Stakes::default();
+        assert!(!fight.roll_save(2, Ability::Wisdom, 50, no_stakes));
+        fight.spell = hold_person.spell().cloned();
+        assert!(fight.roll_save(2, Ability::Wisdom, 50, no_stakes));
+
+        //The effects keep the spell that created them
+        let hold = EffectTemplate {
+            name: "Hold Person".to_string(),
+            conditions: vec![Condition::Paralyzed],
+            ..Default::default()
+        };
+        fight.apply_effect(&hold, 0, 2);
+        assert_eq!(fight.get_effects()[0].get_spell(), hold_person.spell());
+
+        //The Mage frees its ally
+        let component = &dispel_magic.get_components()[0];
+        assert_eq!(mage.get_targets(&fight, component), vec![2]);
+        assert_eq!(dispel_magic.dispel_value(&[10.0]), 10.0);
+        fight.spell = dispel_magic.spell().cloned();
+        component.apply(1, 2, &mut fight);
+        assert!(fight.get_effects().is_empty());
+        assert!(!fight.get_entity(2).unwrap().borrow().is_incapacitated());
+        //A higher level spell needs a check
+        fight.spell = Some(SpellInfo { level: 9, ..hold_person.spell().cloned().unwrap() });
+        fight.apply_effect(&hold, 0, 2);
+        fight.dispel(2, 3, -100);
+        assert_eq!(fight.get_effects().len(), 1);
+        fight.dispel(2, 3, 100);
+        assert!(fight.get_effects().is_empty());
+
+        //Limited Magic Immunity, the Rakshasa isn't a target of the spells
+        fight.spell = ice_knife.spell().cloned();
+        let targets = mage.get_targets(&fight, &ice_knife.get_components()[1]);
+        assert_eq!(targets, vec![0]);
+        fight.spell = None;
+        let targets = mage.get_targets(&fight, &ice_knife.get_components()[1]);
+        assert_eq!(targets, vec![0, 3]);
+    }
+    //Actions played by a creature during one of its turns
+    type Turn = (i32, Vec<String>);
+    struct Turns(Rc<RefCell<Vec<Turn>>>);
+    impl Observer for Turns {
+        fn notify(&mut self, event: &Event, _: &mut Fight) {
+            let mut turns = self.0.borrow_mut();
+            match event.event_type {
+                EventType::Turn if event.timing == Timing::Start => turns.push((event.source_id, Vec::new())),
+                EventType::Action => {
+                    //The legendary actions are played during the turn of another creature
+                    if let Some((_, actions)) = turns.last_mut().filter(|(id, _)| *id == event.source_id) {
+                        actions.push(event.action.clone().unwrap_or_default());
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+    #[test]
+    fn action_economy() {
+        let mut builder = builder();
+        let monsters = vec![
+            builder.create("Monk").hp(500).team(0).build(),
+            builder.create("Kelani").hp(500).team(0).build(),
+            builder.create("Mage").hp(500).team(1).build(),
+            builder.create("Gobelin").hp(500).team(1).build(),
+        ];
+        let mut fight = Fight::with_seed(monsters, 1);
+        let actions = fight
+            .get_entities()
+            .iter()
+            .map(|e| e.borrow().actions().clone())
+            .collect::<Vec<_>>();
+        let turns = Rc::new(RefCell::new(Vec::new()));
+        fight.subscribe(Box::new(Turns(turns.clone())));
+        for _ in 0..4 {
+            fight.advance_round();
+        }
+        let turns = turns.borrow();
+        assert_eq!(turns.len(), 16);
+        let mut bonus_attacks = 0;
+        for (id, played) in turns.iter() {
+            let played = played.iter().map(|name| &actions[*id as usize][name]).collect::<Vec<_>>();
+            let spent = |resource| {
+                played
+                    .iter()
+                    .filter(|action| action.resources().contains(&resource))
+                    .count()
+            };
+            //At most one action, one bonus action and one leveled spell each turn
+            assert!(spent(Resource::Action) <= 1);
+            assert!(spent(Resource::BonusAction) <= 1);
+            assert!(played.iter().filter(|action| action.spell_level() > Some(0)).count() <= 1);
+            //The bonus attacks only after the Attack action
+            for (i, action) in played.iter().enumerate() {
+                if action.resources().contains(&Resource::AttackAction) {
+                    assert!(played[..i].iter().any(|action| action.is_attack_action()));
+                    bonus_attacks += 1;
+                }
+            }
+        }
+        //The Monk follows its attacks with a bonus attack, unless it is held
+        assert!(bonus_attacks > 0);
+
+        //A bonus action spell leaves only the cantrips for the action
+        let healing_word = builder.get_spell_template("Healing Word").build();
+        let hold_person = builder.get_spell_template("Hold Person").build();
+        let vicious_mockery = builder.get_spell_template("Vicious Mockery").build();
+        let mut resources = HashMap::from([
+            (Resource::Action, 1),
+            (Resource::BonusAction, 1),
+            (Resource::SpellAction, 1),
+            (Resource::Spell(1), 1),
+            (Resource::Spell(2), 1),
+        ]);
+        healing_word.consume_resources(&mut resources);
+        assert!(!hold_person.is_available(&resources));
+        assert!(vicious_mockery.is_available(&resources));
+        //And the other way around
+        resources.insert(Resource::BonusAction, 1);
+        resources.insert(Resource::SpellAction, 1);
+        hold_person.consume_resources(&mut resources);
+        assert!(!healing_word.is_available(&resources));
+    }
+    #[test]
+    fn hindered_during_turn() {
+        let mut builder = builder();
+        //The caster of the spell comes after the creature playing in the entities
+        let monsters = vec![
+            builder.create("Mage").team(0).initiative(30).build(),
+            builder.create("Gobelin").hp(500).team(1).build(),
+        ];
+        let mut fight = Fight::with_seed(monsters, 1);
+        let mockery = builder.get_spell_template("Vicious Mockery").build();
+        let template = EffectTemplate {
+            name: "Vicious Mockery".to_string(),
+            modifiers: vec![Modifier::new(ModifierType::Attack)],
+            ..Default::default()
+        };
+        fight.spell = mockery.spell().cloned();
+        fight.apply_effect(&template, 1, 0);
+        fight.spell = None;
+        let actions = Rc::new(RefCell::new(Vec::new()));
+        fight.subscribe(Box::new(Actions(actions.clone())));
+        fight.advance_round();
+        assert!(actions.borrow().iter().any(|(id, _)| *id == 0));
+    }
 }
@@ -1,115 +1,299 @@
-use crate::dice::Dice;
-use crate::utils::*;
+use crate::dice::{Dice, DICE_PATTERN};
+use crate::distribution::Distribution;
+use rand::Rng;
 use regex::Regex;
 use serde::{Deserialize, Serialize};
-use std::num::ParseIntError;
-use std::ops::{Add, AddAssign, Div, DivAssign, Mul, MulAssign, Sub, SubAssign};
+use std::fmt;
+use std::ops::{Add, AddAssign, Mul, MulAssign, Neg, Sub, SubAssign};
 use std::str::FromStr;
 
-#[derive(Default, Clone, Copy, Debug, Serialize, Deserialize)]
+/// Why a dice or formula string couldn't be read.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FormulaErrorKind {
+    Empty,
+    ExpectedTerm,
+    ExpectedDice,
+    ExpectedOperator,
+    UnexpectedCharacter(char),
+    NumberTooLarge,
+    NoFaces,
+    RerollEverything,
+    MinimumAboveFaces,
+    SingleFaceExplosion,
+    DuplicateModifier,
+}
+impl fmt::Display for FormulaErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Empty => write!(f, "the formula is empty"),
+            Self::ExpectedTerm => write!(f, "expected a number or dice"),
+            Self::ExpectedDice => write!(f, "expected dice like 2d6"),
+            Self::ExpectedOperator => write!(f, "expected '+' or '-' between two terms"),
+            Self::UnexpectedCharacter(c) => write!(f, "unexpected character '{c}'"),
+            Self::NumberTooLarge => write!(f, "the number is too large"),
+            Self::NoFaces => write!(f, "a die needs at least one face"),
+            Self::RerollEverything => write!(f, "the reroll matches every face of the die"),
+            Self::MinimumAboveFaces => write!(f, "the minimum is above the faces of the die"),
+            Self::SingleFaceExplosion => write!(f, "a die with a single face cannot explode"),
+            Self::DuplicateModifier => write!(f, "the modifier is given twice"),
+        }
+    }
+}
+
+/// Error returned when parsing a `Dice` or a `Formula`.
+/// `position` is the byte offset of the faulty part in `input`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FormulaParseError {
+    input: String,
+    position: usize,
+    kind: FormulaErrorKind,
+}
+impl FormulaParseError {
+    pub fn new(input: &str, position: usize, kind: FormulaErrorKind) -> Self {
+        Self {
+            input: input.to_string(),
+            position,
+            kind,
+        }
+    }
+    pub fn input(&self) -> &str {
+        &self.input
+    }
+    pub fn position(&self) -> usize {
+        self.position
+    }
+    pub fn kind(&self) -> FormulaErrorKind {
+        self.kind
+    }
+}
+impl fmt::Display for FormulaParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "invalid formula \"{}\": {} at position {}",
+            self.input, self.kind, self.position
+        )
+    }
+}
+impl std::error::Error for FormulaParseError {}
+
+#[derive(Default, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Formula {
-    //TODO can be improved with more dices
-    #[serde(deserialize_with = "string_or_struct")]
-    dice: Dice,
+    //One group per kind of dice, so 1d8+1d6+2d8 is stored as [3d8, 1d6]
+    #[serde(default)]
+    dice: Vec<Dice>,
+    #[serde(default)]
     fixed: i32,
 }
 impl Formula {
-    pub fn roll(&self) -> i32 {
-        self.dice.roll() + self.fixed
+    pub fn roll<R: Rng + ?Sized>(&self, rng: &mut R) -> i32 {
+        self.dice.iter().map(|dice| dice.roll(rng)).sum::<i32>() + self.fixed
     }
-    pub fn add_dice(&mut self, amount: i32) {
-        self.dice.add_dice(amount);
+    /// Add `amount` dice of `face_count` faces to the plain group of this kind, or remove them
+    /// if negative. The subtracted groups and the groups with roll modifiers are left alone.
+    pub fn add_dice(&mut self, face_count: i32, amount: i32) {
+        let kind = Dice::new(1, face_count);
+        match self.dice.iter().position(|group| group.same_kind(&kind)) {
+            Some(index) => {
+                self.dice[index].add_dice(amount);
+                if self.dice[index].dice_count() == 0 {
+                    self.dice.remove(index);
+                }
+            }
+            None if amount > 0 => self.dice.push(Dice::new(amount, face_count)),
+            None => {}
+        }
     }
     pub fn add_fixed(&mut self, amount: i32) {
         self.fixed += amount;
     }
+    pub fn dice(&self) -> &[Dice] {
+        &self.dice
+    }
+    pub fn fixed(&self) -> i32 {
+        self.fixed
+    }
+    /// Add a group of dice, merging it with a group of the same kind if there is one.
+    pub fn push_dice(&mut self, dice: Dice) {
+        if dice.dice_count() == 0 {
+            return;
+        }
+        if let Some(group) = self.dice.iter_mut().find(|group| group.same_kind(&dice)) {
+            *group = group.with_count(group.dice_count() + dice.dice_count());
+        } else {
+            self.dice.push(dice);
+        }
+    }
+    /// The dice rolled again on a critical hit: every die of the formula, without its fixed
+    /// part, and `extra_dice` more of the first die for riders like Brutal Critical.
+    pub fn critical(&self, extra_dice: i32) -> Self {
+        let mut critical = Self {
+            dice: self.dice.clone(),
+            fixed: 0,
+        };
+        if let Some(dice) = self.dice.iter().find(|dice| !dice.is_negative()) {
+            critical.push_dice(dice.with_count(extra_dice.max(0)));
+        }
+        critical
+    }
     pub fn is_formula(s: &str) -> bool {
-        let reg = Regex::new(r"^([+\-]?[0-9]+)$|^[0-9]+d[1-9][0-9]*([+\-][0-9]*|)").unwrap();
-        reg.find(s).is_some()
+        s.parse::<Self>().is_ok()
+    }
+    /// Exact distribution of the formula, the convolution of all its groups of dice.
+    pub fn distribution(&self) -> Distribution {
+        self.dice
+            .iter()
+            .fold(Distribution::constant(self.fixed), |acc, dice| {
+                acc.convolve(&dice.distribution())
+            })
     }
     pub fn average_roll(&self) -> f32 {
-        self.dice.average_roll() + self.fixed as f32
+        self.dice
+            .iter()
+            .map(|dice| dice.average_roll())
+            .sum::<f32>()
+            + self.fixed as f32
+    }
+}
+impl fmt::Display for Formula {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, dice) in self.dice.iter().enumerate() {
+            if i > 0 && !dice.is_negative() {
+                write!(f, "+")?;
+            }
+            write!(f, "{dice}")?;
+        }
+        if self.dice.is_empty() {
+            write!(f, "{}", self.fixed)
+        } else if self.fixed != 0 {
+            write!(f, "{:+}", self.fixed)
+        } else {
+            Ok(())
+        }
     }
 }
 impl From<i32> for Formula {
     fn from(value: i32) -> Self {
-        Self { dice: Dice::default(), fixed: value }
+        Self {
+            dice: Vec::new(),
+            fixed: value,
+        }
+    }
+}
+impl From<Dice> for Formula {
+    fn from(value: Dice) -> Self {
+        let mut formula = Self::default();
+        formula.push_dice(value);
+        formula
     }
 }
-impl From<&str> for Formula {
-    fn from(item: &str) -> Self {
+impl FromStr for Formula {
+    type Err = FormulaParseError;
+    fn from_str(item: &str) -> Result<Self, Self::Err> {
         /*
-         * Regex capture 3d6+2, 3d6-2, 3d6
-         * Since there is 2 capture zone, then capture will have 2 capture area, one for each
+         * Read the terms one by one: 3d6, +2, -1d4, +4d6kh3, ...
+         * Only the first term may omit its sign.
          */
-        let reg_fixed = Regex::new(r"^([+\-]?[0-9]+)$|^[0-9]+d[1-9][0-9]*([+\-][0-9]*|)").unwrap();
-        if let Some(capture) = reg_fixed.captures(item) {
-            let (fixed, dice) = if let Some(m) = capture.get(1) {
-                (m.as_str().parse::<i32>().unwrap_or(0), Dice::new(0, 1))
-            } else if let Some(m) = capture.get(2) {
-                (m.as_str().parse::<i32>().unwrap_or(0), Dice::from(item))
-            } else {
-                unreachable!("Formula::from: regex matched bu no pattern are available.");
+        let reg_term =
+            Regex::new(&format!(r"^\s*([+\-]?)\s*(?:{DICE_PATTERN}|([0-9]+))\s*")).unwrap();
+        let error = |position, kind| FormulaParseError::new(item, position, kind);
+        if item.trim().is_empty() {
+            return Err(error(0, FormulaErrorKind::Empty));
+        }
+        let mut formula = Self::default();
+        let mut position = 0;
+        while position < item.len() {
+            let rest = &item[position..];
+            let Some(capture) = reg_term.captures(rest) else {
+                let skipped = rest.len() - rest.trim_start_matches([' ', '+', '-']).len();
+                return Err(error(position + skipped, FormulaErrorKind::ExpectedTerm));
             };
-            Self { dice, fixed }
-        } else {
-            panic!("The Formula string has the wrong format : {item}");
+            //The term starts at the dice count or at the fixed number
+            let start = position + capture.get(2).or(capture.get(5)).unwrap().start();
+            if position > 0 && capture[1].is_empty() {
+                return Err(error(start, FormulaErrorKind::ExpectedOperator));
+            }
+            let sign = if &capture[1] == "-" { -1 } else { 1 };
+            if capture.get(3).is_some() {
+                let dice = Dice::from_parts(&capture[2], &capture[3], &capture[4])
+                    .map_err(|(offset, kind)| error(start + offset, kind))?;
+                formula.push_dice(dice.with_count(sign * dice.dice_count()));
+            } else {
+                let fixed = capture[5]
+                    .parse::<i32>()
+                    .map_err(|_| error(start, FormulaErrorKind::NumberTooLarge))?;
+                formula.fixed += sign * fixed;
+            }
+            position += capture.get(0).unwrap().end();
         }
+        Ok(formula)
     }
 }
-impl From<&String> for Formula {
-    fn from(item: &String) -> Self {
-        Self::from(item.as_str())
-    }
-}
-impl FromStr for Formula {
-    type Err = ParseIntError;
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        Ok(Self::from(s))
+impl Neg for Formula {
+    type Output = Self;
+    fn neg(self) -> Self {
+        Self {
+            dice: self
+                .dice
+                .into_iter()
+                .map(|dice| dice.with_count(-dice.dice_count()))
+                .collect(),
+            fixed: -self.fixed,
+        }
     }
 }
 impl Add for Formula {
     type Output = Self;
-    fn add(self, other: Self) -> Self {
-        Self {
-            dice: self.dice + other.dice,
-            fixed: self.fixed + other.fixed,
-        }
+    fn add(mut self, other: Self) -> Self {
+        self += other;
+        self
     }
 }
 impl AddAssign for Formula {
     fn add_assign(&mut self, other: Self) {
-        self.dice += other.dice;
+        for dice in other.dice {
+            self.push_dice(dice);
+        }
         self.fixed += other.fixed;
     }
 }
+impl AddAssign<&Formula> for Formula {
+    fn add_assign(&mut self, other: &Self) {
+        *self += other.clone();
+    }
+}
 impl Sub for Formula {
     type Output = Self;
     fn sub(self, other: Self) -> Self {
-        Self {
-            dice: self.dice - other.dice,
-            fixed: self.fixed - other.fixed,
-        }
+        self + -other
     }
 }
 impl SubAssign for Formula {
     fn sub_assign(&mut self, other: Self) {
-        self.dice -= other.dice;
-        self.fixed -= other.fixed;
+        *self += -other;
     }
 }
 impl Mul<i32> for Formula {
     type Output = Self;
-    fn mul(self, other: i32) -> Self {
-        Self {
-            dice: self.dice * other,
-            fixed: self.fixed * other,
-        }
+    fn mul(mut self, other: i32) -> Self {
+        self *= other;
+        self
     }
 }
 impl MulAssign<i32> for Formula {
     fn mul_assign(&mut self, other: i32) {
-        self.dice *= other;
+        //Dice keeping only some of their results are repeated instead of growing,
+        //and a negative factor subtracts them
+        let sign = other.signum();
+        self.dice = self
+            .dice
+            .iter()
+            .flat_map(|dice| match dice.keep() {
+                Some(_) => vec![dice.with_count(dice.dice_count() * sign); other.unsigned_abs() as usize],
+                None => vec![dice.with_count(dice.dice_count() * other)],
+            })
+            .filter(|dice| dice.dice_count() != 0)
+            .collect();
         self.fixed *= other;
     }
 }
@@ -117,26 +301,138 @@ impl MulAssign<i32> for Formula {
 #[cfg(test)]
 mod tests {
     use super::*;
+    fn formula(s: &str) -> Formula {
+        s.parse().unwrap()
+    }
     #[test]
     fn from() {
-        let f = Formula::from("-31");
+        let f = formula("-31");
         assert_eq!(f.fixed, -31);
-        assert_eq!(f.dice.dice_count(), 0);
-        let f = Formula::from("7");
+        assert!(f.dice.is_empty());
+        let f = formula("7");
         assert_eq!(f.fixed, 7);
-        assert_eq!(f.dice.dice_count(), 0);
-        let f = Formula::from("3d6");
+        assert!(f.dice.is_empty());
+        let f = formula("3d6");
         assert_eq!(f.fixed, 0);
-        assert_eq!(f.dice.face_count(), 6);
-        assert_eq!(f.dice.dice_count(), 3);
-        let f = Formula::from("2d7+8");
+        assert_eq!(f.dice[0].face_count(), 6);
+        assert_eq!(f.dice[0].dice_count(), 3);
+        let f = formula("2d7+8");
         assert_eq!(f.fixed, 8);
-        assert_eq!(f.dice.face_count(), 7);
-        assert_eq!(f.dice.dice_count(), 2);
-        let f = Formula::from("30d20-10");
+        assert_eq!(f.dice[0].face_count(), 7);
+        assert_eq!(f.dice[0].dice_count(), 2);
+        let f = formula("30d20-10");
         assert_eq!(f.fixed, -10);
-        assert_eq!(f.dice.face_count(), 20);
-        assert_eq!(f.dice.dice_count(), 30);
+        assert_eq!(f.dice[0].face_count(), 20);
+        assert_eq!(f.dice[0].dice_count(), 30);
+    }
+    #[test]
+    fn from_multiple_dice() {
+        let f = formula("1d8+1d6+3");
+        assert_eq!(f.dice, vec![Dice::new(1, 8), Dice::new(1, 6)]);
+        assert_eq!(f.fixed, 3);
+        let f = formula("2d6 + 1d4 - 1d6 + 2 - 5");
+        assert_eq!(
+            f.dice,
+            vec![Dice::new(2, 6), Dice::new(1, 4), Dice::new(-1, 6)]
+        );
+        assert_eq!(f.fixed, -3);
+        let f = formula("d20+1d20");
+        assert_eq!(f.dice, vec![Dice::new(2, 20)]);
+        assert_eq!(f.to_string(), "2d20");
+        assert_eq!(formula("1d8+1d6+3").to_string(), "1d8+1d6+3");
+        assert_eq!(formula("1d8-1d4").to_string(), "1d8-1d4");
+    }
+    #[test]
+    fn from_str_errors() {
+        let error = "1d8 1d6".parse::<Formula>().unwrap_err();
+        assert_eq!(error.kind(), FormulaErrorKind::ExpectedOperator);
+        assert_eq!(error.position(), 4);
+        let error = "3d6+".parse::<Formula>().unwrap_err();
+        assert_eq!(error.kind(), FormulaErrorKind::ExpectedTerm);
+        assert_eq!(error.position(), 4);
+        let error = "2d6 + 1d0".parse::<Formula>().unwrap_err();
+        assert_eq!(error.kind(), FormulaErrorKind::NoFaces);
+        assert_eq!(error.position(), 8);
+        let error = "1d8+2d6r<=6".parse::<Formula>().unwrap_err();
+        assert_eq!(error.kind(), FormulaErrorKind::RerollEverything);
+        assert_eq!(error.position(), 7);
+        let error = "  ".parse::<Formula>().unwrap_err();
+        assert_eq!(error.kind(), FormulaErrorKind::Empty);
+        let error = "1d6+99999999999".parse::<Formula>().unwrap_err();
+        assert_eq!(error.kind(), FormulaErrorKind::NumberTooLarge);
+        assert_eq!(
+            error.to_string(),
+            "invalid formula \"1d6+99999999999\": the number is too large at position 4"
+        );
+    }
+    #[test]
+    fn deserialize_error() {
+        use crate::template::MonsterStatsTemplate;
+        let json = r#"{
+            "abilities": {"strength": 8, "dexterity": 14, "constitution": 10,
+                          "intelligence": 10, "wisdom": 8, "charisma": 8},
+            "saving_throws": {"strength": 8, "dexterity": 14, "constitution": 10,
+                              "intelligence": 10, "wisdom": 8, "charisma": 8},
+            "initiative": 14, "armor_class": 15, "hp": "2d6+x"
+        }"#;
+        let error = serde_json::from_str::<MonsterStatsTemplate>(json).unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("invalid formula \"2d6+x\": expected a number or dice at position 4"));
+        let json = json.replace("2d6+x", "2d6+2");
+        let stats = serde_json::from_str::<MonsterStatsTemplate>(&json).unwrap();
+        assert_eq!(stats.hp, formula("2d6+2"));
+    }
+    #[test]
+    fn operations() {
+        let mut f = formula("1d8+3");
+        f += formula("1d6");
+        f += formula("1d8");
+        assert_eq!(f, formula("2d8+1d6+3"));
+        assert_eq!(formula("1d10") * 3, formula("3d10"));
+        assert_eq!(Formula::default() + formula("1d6"), formula("1d6"));
+        assert_eq!(formula("2d6") - formula("1d4"), formula("2d6-1d4"));
+        assert_eq!(formula("1d8+1d6+3").average_roll(), 11.0);
+        assert_eq!(formula("4d6kh3") * 2, formula("4d6kh3+4d6kh3"));
+        assert_eq!(formula("4d6kh3+4d6kh3").dice().len(), 2);
+        assert_eq!(formula("2d6+1d8+3").critical(0), formula("2d6+1d8"));
+        assert_eq!(formula("2d6+1d8+3").critical(1), formula("3d6+1d8"));
+        assert_eq!(formula("5").critical(2), formula("0"));
+        //A negative factor subtracts the dice, kept or not
+        assert_eq!(formula("4d6kh3+1d4+2") * -2, formula("-4d6kh3-4d6kh3-2d4-4"));
+    }
+    #[test]
+    fn add_dice() {
+        let mut f = formula("2d6+1d8");
+        f.add_dice(8, 2);
+        assert_eq!(f, formula("2d6+3d8"));
+        //The subtracted groups stay as they are
+        let mut f = formula("-1d4+2d6");
+        f.add_dice(4, 1);
+        f.add_dice(6, -1);
+        assert_eq!(f, formula("-1d4+1d6+1d4"));
+        f.add_dice(6, -3);
+        assert_eq!(f, formula("-1d4+1d4"));
+        f.add_dice(10, -1);
+        assert_eq!(f, formula("-1d4+1d4"));
+    }
+    #[test]
+    fn roll_is_reproducible() {
+        use rand::{rngs::StdRng, SeedableRng};
+        let f = formula("4d6kh3+1d8!+2d6ro<=2");
+        let mut a = StdRng::seed_from_u64(42);
+        let mut b = StdRng::seed_from_u64(42);
+        let rolls_a: Vec<i32> = (0..50).map(|_| f.roll(&mut a)).collect();
+        let rolls_b: Vec<i32> = (0..50).map(|_| f.roll(&mut b)).collect();
+        assert_eq!(rolls_a, rolls_b);
+    }
+    #[test]
+    fn roll_modifiers() {
+        let f = formula("2d6ro<=2+1d8min2+1d6!-4d6dl1+3");
+        assert_eq!(f.to_string(), "2d6ro<=2+1d8min2+1d6!-4d6dl1+3");
+        assert!(Formula::is_formula("4d6kh3+2d20kl1"));
+        assert!(Formula::is_formula("2d6r<=2"));
+        assert!(!Formula::is_formula("2d6r<=2x"));
     }
     #[test]
     fn is_formula() {
@@ -153,5 +449,8 @@ mod tests {
         assert!(Formula::is_formula("0d6+7"));
         assert!(Formula::is_formula("0d6+0"));
         assert!(!Formula::is_formula("6d0+7"));
+        assert!(Formula::is_formula("1d8+1d6+3"));
+        assert!(Formula::is_formula("2d6-1d4"));
+        assert!(!Formula::is_formula("2d6+"));
     }
 }
@@ -1,8 +1,12 @@
 #![feature(variant_count)]
+#![allow(non_snake_case)]
 pub mod utils;
 pub mod fight;
 pub mod monster;
+pub mod condition;
+pub mod damage;
 pub mod dice;
+pub mod distribution;
 pub mod action;
 pub mod resource;
 pub mod formula;
@@ -14,3 +18,5 @@ pub mod modifier;
 pub mod effect;
 pub mod duration;
 pub mod event;
+pub mod reaction;
+pub mod spell;
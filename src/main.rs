@@ -1,3 +1,4 @@
+#![allow(non_snake_case)]
 use std::collections::HashMap;
 use std::fs::File;
 use std::io::Read;
@@ -8,6 +9,13 @@ use BattleForge::template::SpellTemplate;
 use BattleForge::template::TemplateBuilder;
 
 fn main() {
+    //Replay a fight by passing the seed it printed
+    let seed = std::env::args()
+        .nth(1)
+        .map(|s| s.parse::<u64>().expect("The seed must be a number"))
+        .unwrap_or_else(rand::random);
+    println!("Seed: {seed}");
+
     let j = serde_json::to_string(&Charge::Infinite).unwrap();
     println!("{}", j);
     let j = serde_json::to_string(&Charge::Limited(3)).unwrap();
@@ -20,16 +28,25 @@ fn main() {
     let mut file = File::open("gobelin.json").unwrap();
     let mut data = String::new();
     file.read_to_string(&mut data).unwrap();
-    let monster_database: Vec<MonsterTemplate> = serde_json::from_str(&data).unwrap();
+    let monster_database: Vec<MonsterTemplate> =
+        serde_json::from_str(&data).unwrap_or_else(|error| {
+            eprintln!("gobelin.json: {error}");
+            std::process::exit(1);
+        });
 
     let mut file = File::open("spells.json").unwrap();
     let mut data = String::new();
     file.read_to_string(&mut data).unwrap();
-    let spell_database: HashMap<String, SpellTemplate> = serde_json::from_str(&data).unwrap();
+    let spell_database: HashMap<String, SpellTemplate> = serde_json::from_str(&data)
+        .unwrap_or_else(|error| {
+            eprintln!("spells.json: {error}");
+            std::process::exit(1);
+        });
     println!("{:?}", spell_database);
     // println!("{:?}", monster_database);
 
     let mut builder = TemplateBuilder::new(monster_database, spell_database);
+    builder.seed(seed);
 
     // let kelani = builder.create("Kelani").team(0).build();
     // println!("{:?}", kelani);
@@ -40,7 +57,7 @@ fn main() {
         // builder.create("Black Bear").team(1).build(),
         builder.create("Kelani").team(1).build(),
     ];
-    let mut fight = Fight::new(monsters);
+    let mut fight = Fight::with_seed(monsters, seed);
     let winner = fight.play();
     println!("Winner is {winner:?}");
 }
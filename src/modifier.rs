@@ -1,4 +1,5 @@
 use rand::distributions::{Distribution, Uniform};
+use rand::Rng;
 use serde::{Deserialize, Serialize};
 use std::ops::{Add, AddAssign};
 
@@ -12,23 +13,89 @@ pub enum ModifierType {
     #[default]
     Attack,
     Attacked,
+    //Saves to keep the concentration, on top of the Constitution saves
+    Concentration,
+    //Saves against spells, on top of the ability saves, like Magic Resistance
+    SpellSave,
 }
 
 #[derive(Default, Clone, Debug, Serialize, Deserialize)]
 pub struct Modifier {
     //NOTE formulas should probably include a modifier of the target? Maybe, maybe not.
+    #[serde(default)]
     formulas: Vec<Formula>,
+    #[serde(default)]
     formula_multipliers: Vec<i32>,
     mod_type: ModifierType,
-    advantage: i32, //positive means has advantage and negative means has disadvantage
+    //Number of sources of advantage and disadvantage, any of both cancel each other
+    #[serde(default)]
+    advantage: i32,
+    #[serde(default)]
+    disadvantage: i32,
+}
+
+/// Result of a d20 roll, the natural die is kept to detect critical hits and misses.
+#[derive(Default, Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Throw {
+    natural: i32,
+    total: i32,
+}
+impl Throw {
+    pub fn natural(&self) -> i32 {
+        self.natural
+    }
+    pub fn total(&self) -> i32 {
+        self.total
+    }
 }
 
 impl Modifier {
-    pub fn roll(&self) -> i32 {
-        let mut rng = rand::thread_rng();
+    pub fn new(mod_type: ModifierType) -> Self {
+        Self {
+            mod_type,
+            ..Default::default()
+        }
+    }
+    pub fn mod_type(&self) -> ModifierType {
+        self.mod_type
+    }
+    /// Add `multiplier` times `formula` to the roll, like Bless (1d4, 1) or Bane (1d4, -1).
+    pub fn add_formula(&mut self, formula: Formula, multiplier: i32) {
+        self.formulas.push(formula);
+        self.formula_multipliers.push(multiplier);
+    }
+    pub fn add_advantage(&mut self) {
+        self.advantage += 1;
+    }
+    pub fn add_disadvantage(&mut self) {
+        self.disadvantage += 1;
+    }
+    pub fn has_advantage(&self) -> bool {
+        self.advantage > 0 && self.disadvantage == 0
+    }
+    pub fn has_disadvantage(&self) -> bool {
+        self.disadvantage > 0 && self.advantage == 0
+    }
+    pub fn roll<R: Rng + ?Sized>(&self, rng: &mut R) -> Throw {
         let die = Uniform::from(1..=20);
-        let throw = die.sample(&mut rng);
-        todo!("Modifier::roll");
+        let throw = die.sample(rng);
+        let natural = if self.has_advantage() {
+            throw.max(die.sample(rng))
+        } else if self.has_disadvantage() {
+            throw.min(die.sample(rng))
+        } else {
+            throw
+        };
+        let bonus: i32 = self
+            .formulas
+            .iter()
+            .zip(&self.formula_multipliers)
+            .map(|(formula, multiplier)| formula.roll(rng) * multiplier)
+            .sum();
+        Throw {
+            natural,
+            total: natural + bonus,
+        }
     }
 }
 impl From<i32> for Modifier {
@@ -38,17 +105,66 @@ impl From<i32> for Modifier {
             formula_multipliers: vec![1],
             mod_type: ModifierType::default(),
             advantage: 0,
+            disadvantage: 0,
         }
     }
 }
 impl AddAssign<&Modifier> for Modifier {
     fn add_assign(&mut self, other: &Self) {
-        todo!("Modifier::add_assign");
+        //Bonuses from different sources stack, advantages are only counted
+        self.formulas.extend(other.formulas.iter().cloned());
+        self.formula_multipliers
+            .extend(other.formula_multipliers.iter().copied());
+        self.advantage += other.advantage;
+        self.disadvantage += other.disadvantage;
     }
 }
 impl Add for Modifier {
     type Output = Self;
-    fn add(self, other: Self) -> Self {
-        todo!("Modifier::add (Add trait)");
+    fn add(mut self, other: Self) -> Self {
+        self += &other;
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, SeedableRng};
+    fn formula(s: &str) -> Formula {
+        s.parse().unwrap()
+    }
+    #[test]
+    fn advantage() {
+        let mut advantage = Modifier::default();
+        advantage.add_advantage();
+        let mut disadvantage = Modifier::default();
+        disadvantage.add_disadvantage();
+        let mut rng = StdRng::seed_from_u64(7);
+        let die = Uniform::from(1..=20);
+        for _ in 0..100 {
+            let mut copy = rng.clone();
+            let (a, b) = (die.sample(&mut copy), die.sample(&mut copy));
+            assert_eq!(advantage.roll(&mut rng.clone()).natural(), a.max(b));
+            assert_eq!(disadvantage.roll(&mut rng.clone()).natural(), a.min(b));
+            //Two advantages and a disadvantage still cancel each other
+            let both = advantage.clone() + advantage.clone() + disadvantage.clone();
+            assert_eq!(both.roll(&mut rng).natural(), a);
+        }
+    }
+    #[test]
+    fn bonus_formulas() {
+        let mut bless = Modifier::new(ModifierType::Attack);
+        bless.add_formula(formula("1d4"), 1);
+        let mut bane = Modifier::new(ModifierType::Attack);
+        bane.add_formula(formula("1d4"), -1);
+        let mut rng = StdRng::seed_from_u64(3);
+        for _ in 0..100 {
+            let throw = (bless.clone() + Modifier::from(5)).roll(&mut rng);
+            assert!((1..=20).contains(&throw.natural()));
+            assert!((6..=9).contains(&(throw.total() - throw.natural())));
+            let throw = (bless.clone() + bane.clone()).roll(&mut rng);
+            assert!((-3..=3).contains(&(throw.total() - throw.natural())));
+        }
     }
 }
@@ -1,9 +1,16 @@
 use crate::ability::Ability;
 use crate::template::MonsterStatsTemplate;
-use crate::{action::*, fight::Fight, float::*, resource::*, template::*, utils::*};
+use crate::condition::Condition;
+use crate::damage::{Damage, DamageType};
+use crate::effect::Effect;
+use crate::modifier::{Modifier, ModifierType};
+use crate::reaction::{Reaction, ReactionKind};
+use crate::spell::SpellInfo;
+use crate::{action::*, dice::Dice, fight::Fight, float::*, resource::*, template::*, utils::*};
+use rand::Rng;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::iter::once;
+use std::iter::{once, repeat_n};
 use std::mem;
 
 #[derive(Default, Clone, Debug)]
@@ -14,9 +21,52 @@ pub struct Monster {
     team_id: u8,
     actions: HashMap<String, ActionStruct>,
     resources: HashMap<Resource, i32>,
+    critical_range: Option<i32>,
+    critical_extra_dice: i32,
+    //Player characters fall unconscious at 0 hp instead of dying
+    player: bool,
+    death_saves: DeathSaves,
+    dead: bool,
+    //A condition is there once per source applying it
+    conditions: Vec<Condition>,
+    modifiers: Vec<Modifier>,
+    //Legendary actions regained at the start of its turn
+    legendary_actions: i32,
+    lair_actions: HashMap<String, ActionStruct>,
+    reactions: Vec<Reaction>,
+    //Highest level of the spells it is immune to
+    spell_immunity: Option<i32>,
+    //Free actions played this turn
+    free_actions: Vec<String>,
+}
+
+/// What a creature loses by failing a save, valued like the AI values the actions.
+#[derive(Default, Clone, Copy, Debug, PartialEq)]
+pub struct Stakes {
+    pub damage: f32,
+    pub effect: f32,
+}
+
+/// Death saving throws of a player character at 0 hp.
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DeathSaves {
+    successes: i32,
+    failures: i32,
+}
+impl DeathSaves {
+    pub fn successes(&self) -> i32 {
+        self.successes
+    }
+    pub fn failures(&self) -> i32 {
+        self.failures
+    }
 }
 impl Monster {
-    pub fn from_template(builder: &TemplateBuilder, template: &MonsterTemplate) -> Self {
+    pub fn from_template<R: Rng + ?Sized>(
+        builder: &TemplateBuilder,
+        template: &MonsterTemplate,
+        rng: &mut R,
+    ) -> Self {
         //Create resources of the monster from normal resources + additional (spell slots, Ki, ...)
         //NOTE one monster may have multiple actions if the database says so
         let resources = template
@@ -26,6 +76,9 @@ impl Monster {
             .chain(once(Resource::Action))
             .chain(once(Resource::BonusAction))
             .chain(once(Resource::SpellAction))
+            .chain(once(Resource::Reaction))
+            .chain(repeat_n(Resource::LegendaryAction, template.legendary_actions as usize))
+            .chain(repeat_n(Resource::LegendaryResistance, template.legendary_resistances as usize))
             .fold(HashMap::new(), |mut hash, res| {
                 hash.entry(res).and_modify(|e| *e += 1).or_insert(1);
                 hash
@@ -34,10 +87,22 @@ impl Monster {
         let mut monster = Self {
             id: 0,
             name: template.name.clone(),
-            entity_stats: MonsterStats::from_template(builder, &template.entity_stats),
+            entity_stats: MonsterStats::from_template(builder, &template.entity_stats, rng),
             team_id: 0,
             actions: HashMap::new(),
             resources,
+            critical_range: template.critical_range,
+            critical_extra_dice: template.critical_extra_dice,
+            player: template.player,
+            death_saves: DeathSaves::default(),
+            dead: false,
+            conditions: Vec::new(),
+            modifiers: template.modifiers.clone(),
+            legendary_actions: template.legendary_actions,
+            lair_actions: HashMap::new(),
+            reactions: template.reactions.clone(),
+            spell_immunity: template.spell_immunity,
+            free_actions: Vec::new(),
         };
         //Create the action using monster to parametrize them
         let actions = template
@@ -48,6 +113,14 @@ impl Monster {
             })
             .collect();
         monster.actions = actions;
+        let lair_actions = template
+            .lair_actions
+            .iter()
+            .flat_map(|action_template| {
+                ActionStruct::from_template(builder, &monster, action_template)
+            })
+            .collect();
+        monster.lair_actions = lair_actions;
         monster
     }
     pub fn team(&self) -> u8 {
@@ -56,12 +129,105 @@ impl Monster {
     pub fn set_team(&mut self, team: u8) {
         self.team_id = team;
     }
+    pub fn is_player(&self) -> bool {
+        self.player
+    }
+    pub fn set_player(&mut self, player: bool) {
+        self.player = player;
+    }
     pub fn is_dead(&self) -> bool {
-        self.entity_stats.hp() <= 0
+        if self.player {
+            self.dead
+        } else {
+            self.entity_stats.hp() <= 0
+        }
     }
     pub fn is_alive(&self) -> bool {
+        !self.is_dead()
+    }
+    /// Whether the spell can't affect the monster.
+    pub fn is_immune_to(&self, spell: &SpellInfo) -> bool {
+        self.spell_immunity.is_some_and(|level| spell.level <= level)
+    }
+    /// Whether the monster is up, the others can't act.
+    pub fn is_conscious(&self) -> bool {
         self.entity_stats.hp() > 0
     }
+    /// A player character at 0 hp with three successful death saves, no longer rolling them.
+    pub fn is_stable(&self) -> bool {
+        self.is_alive() && !self.is_conscious() && self.death_saves.successes >= 3
+    }
+    /// A player character at 0 hp still rolling death saves.
+    pub fn is_dying(&self) -> bool {
+        self.is_alive() && !self.is_conscious() && !self.is_stable()
+    }
+    pub fn add_condition(&mut self, condition: Condition) {
+        self.conditions.push(condition);
+    }
+    /// Remove the condition once, it stays if another source applied it too.
+    pub fn remove_condition(&mut self, condition: Condition) -> bool {
+        if let Some(index) = self.conditions.iter().position(|c| *c == condition) {
+            self.conditions.remove(index);
+            true
+        } else {
+            false
+        }
+    }
+    /// The conditions of the monster, with the ones they imply, and Unconscious at 0 hp.
+    pub fn conditions(&self) -> Vec<Condition> {
+        let mut conditions = self.conditions.clone();
+        if !self.is_conscious() && self.is_alive() {
+            conditions.push(Condition::Unconscious);
+        }
+        let implied = conditions
+            .iter()
+            .flat_map(|condition| condition.implies())
+            .copied()
+            .collect::<Vec<_>>();
+        conditions.extend(implied);
+        let mut unique = Vec::new();
+        for condition in conditions {
+            if !unique.contains(&condition) {
+                unique.push(condition);
+            }
+        }
+        unique
+    }
+    pub fn has_condition(&self, condition: Condition) -> bool {
+        self.conditions().contains(&condition)
+    }
+    /// An incapacitated monster can't take actions.
+    pub fn is_incapacitated(&self) -> bool {
+        self.has_condition(Condition::Incapacitated)
+    }
+    pub fn death_saves(&self) -> DeathSaves {
+        self.death_saves
+    }
+    /// Roll a death save at the start of the turn of a dying player character.
+    /// A natural 20 brings it back with 1 hp, and a natural 1 counts as two failures.
+    pub fn roll_death_save<R: Rng + ?Sized>(&mut self, rng: &mut R) {
+        if !self.is_dying() {
+            return;
+        }
+        let roll = Dice::new(1, 20).roll(rng);
+        eprintln!("{} rolls a death save: {roll}", self.name);
+        match roll {
+            20 => self.increase_hp(1),
+            10.. => self.death_saves.successes += 1,
+            1 => self.fail_death_saves(2),
+            _ => self.fail_death_saves(1),
+        }
+    }
+    fn fail_death_saves(&mut self, failures: i32) {
+        //Taking damage while stable starts the death saves again
+        if self.is_stable() {
+            self.death_saves = DeathSaves::default();
+        }
+        self.death_saves.failures += failures;
+        if self.death_saves.failures >= 3 {
+            self.dead = true;
+        }
+    }
     pub fn ac(&self) -> i32 {
         self.entity_stats.armor_class()
     }
@@ -71,65 +237,288 @@ impl Monster {
     pub fn hp(&self) -> i32 {
         self.entity_stats.hp()
     }
+    pub fn max_hp(&self) -> i32 {
+        self.entity_stats.max_hp()
+    }
+    pub fn temp_hp(&self) -> i32 {
+        self.entity_stats.temp_hp()
+    }
+    /// Gain `amount` temporary hit points, unless the monster already has more.
+    pub fn add_temp_hp(&mut self, amount: i32) {
+        self.entity_stats.add_temp_hp(amount);
+    }
+    pub fn remove_temp_hp(&mut self) {
+        self.entity_stats.remove_temp_hp();
+    }
     pub fn set_hp(&mut self, hp: i32) {
         self.entity_stats.set_hp(hp);
         self.entity_stats.set_max_hp(hp);
     }
+    pub fn add_modifier(&mut self, modifier: Modifier) {
+        self.modifiers.push(modifier);
+    }
+    /// Modifiers of type `mod_type` the monster always has.
+    pub fn modifiers(&self, mod_type: ModifierType) -> impl Iterator<Item = &Modifier> {
+        self.modifiers.iter().filter(move |modifier| modifier.mod_type() == mod_type)
+    }
+    pub fn critical_range(&self) -> Option<i32> {
+        self.critical_range
+    }
+    pub fn critical_extra_dice(&self) -> i32 {
+        self.critical_extra_dice
+    }
     pub fn set_id(&mut self, id: i32) {
         self.id = id;
     }
     pub fn id(&self) -> i32 {
         self.id
     }
-    pub fn decrease_hp(&mut self, amount: i32) {
-        self.entity_stats.decrease_hp(amount);
+    /// Fix the initiative of the monster instead of rolling it.
+    pub fn set_initiative(&mut self, initiative: i32) {
+        self.entity_stats.fixed_initiative = Some(initiative);
+    }
+    /// Roll d20 + initiative modifier, or return the fixed initiative.
+    pub fn roll_initiative<R: Rng + ?Sized>(&self, rng: &mut R) -> i32 {
+        match self.entity_stats.fixed_initiative {
+            Some(initiative) => initiative,
+            None => Dice::new(1, 20).roll(rng) + self.entity_stats.initiative as i32,
+        }
+    }
+    pub fn ability_mod(&self, ability: Ability) -> i32 {
+        self.entity_stats.ability_mod(ability)
+    }
+    /// Amount of hp `damage` removes once immunities, resistances and vulnerabilities are applied.
+    pub fn damage_taken(&self, damage: Damage) -> i32 {
+        let petrified = self.has_condition(Condition::Petrified);
+        self.entity_stats.damage_taken(damage, petrified)
+    }
+    /// Lose `amount` hp. A player character dropping to 0 hp falls unconscious, unless the damage
+    /// left over reaches its max hp. Damage while at 0 hp is a failed death save, two on a critical hit.
+    pub fn decrease_hp(&mut self, amount: i32, critical: bool) {
+        if self.is_dead() {
+            return;
+        }
+        let was_conscious = self.is_conscious();
+        let overflow = self.entity_stats.decrease_hp(amount);
+        if !self.player || self.is_conscious() {
+            return;
+        }
+        if overflow >= self.max_hp() {
+            eprintln!("{} is killed by massive damage", self.name);
+            self.dead = true;
+        } else if was_conscious {
+            eprintln!("{} falls unconscious", self.name);
+            self.death_saves = DeathSaves::default();
+        } else if overflow > 0 {
+            self.fail_death_saves(if critical { 2 } else { 1 });
+        }
     }
     pub fn increase_hp(&mut self, amount: i32) {
+        if self.is_dead() {
+            return;
+        }
+        if !self.is_conscious() && amount > 0 {
+            eprintln!("{} regains consciousness", self.name);
+            self.death_saves = DeathSaves::default();
+        }
         self.entity_stats.increase_hp(amount);
     }
-    pub fn get_targets(&self, fight: &Fight, action: &ActionComponent) -> Vec<usize> {
-        let target_count = action.target_count() as usize;
+    pub fn get_targets(&self, fight: &Fight, action: &ActionComponent) -> Vec<i32> {
+        let target_count = action.target_count();
+        match action.target() {
+            Target::Enemy => {
+                let mut enemies = fight
+                    .get_entities()
+                    .iter()
+                    .filter_map(|monster| {
+                        let monster = monster.borrow();
+                        //Don't waste a spell on an enemy immune to it
+                        let immune = fight.spell().is_some_and(|spell| monster.is_immune_to(spell));
+                        if monster.team_id != self.team_id && monster.is_alive() && !immune {
+                            Some((monster.is_conscious(), monster.id))
+                        } else {
+                            None
+                        }
+                    })
+                    .collect::<Vec<_>>();
+                //Finish the downed enemies once the others are down too
+                enemies.sort_by_key(|(conscious, _)| !conscious);
+                enemies
+                    .into_iter()
+                    .map(|(_, id)| id)
+                    .take(target_count)
+                    .collect()
+            }
+            Target::Myself => vec![self.id],
+            //Heal the most wounded allies first
+            Target::Ally => {
+                let mut allies = fight
+                    .get_entities()
+                    .iter()
+                    .filter_map(|monster| {
+                        let monster = monster.borrow();
+                        let missing = monster.max_hp() - monster.hp();
+                        if monster.team_id == self.team_id && monster.is_alive() && missing > 0 {
+                            Some((missing, monster.id))
+                        } else {
+                            None
+                        }
+                    })
+                    .collect::<Vec<_>>();
+                allies.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+                allies
+                    .into_iter()
+                    .map(|(_, id)| id)
+                    .take(target_count)
+                    .collect()
+            }
+            //Free the most hindered allies first
+            Target::Hindered => {
+                let mut allies = fight
+                    .get_entities()
+                    .iter()
+                    .filter_map(|monster| {
+                        let monster = monster.borrow();
+                        let hindrance = self.hindrance(fight, monster.id);
+                        if monster.team_id == self.team_id && monster.is_alive() && hindrance > 0.0 {
+                            Some((F32(hindrance), monster.id))
+                        } else {
+                            None
+                        }
+                    })
+                    .collect::<Vec<_>>();
+                allies.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+                allies
+                    .into_iter()
+                    .map(|(_, id)| id)
+                    .take(target_count)
+                    .collect()
+            }
+        }
+    }
+    //Worth of the effects of the enemy spells on the creature `id`
+    fn hindrance(&self, fight: &Fight, id: i32) -> f32 {
         fight
-            .get_entities()
+            .get_effects()
             .iter()
-            .enumerate()
-            .filter_map(|(i, monster)| {
-                let monster = monster.borrow();
-                if monster.team_id != self.team_id && monster.is_alive() {
-                    Some(i)
-                } else {
-                    None
-                }
+            .filter(|effect| effect.targets(id) && effect.get_spell().is_some())
+            .filter(|effect| {
+                //The monster playing is already borrowed by the fight, and it isn't its own enemy
+                fight
+                    .get_entities()
+                    .iter()
+                    .filter_map(|monster| monster.try_borrow().ok())
+                    .any(|source| source.id == effect.source_id() && source.team_id != self.team_id)
             })
-            .take(target_count as usize)
-            .collect::<Vec<_>>()
+            .map(Effect::value)
+            .sum()
     }
-    pub fn take_action(&mut self, fight: &Fight) -> Option<ActionStruct> {
+    //Hindrance of the living allies, and self, most hindered first
+    fn hindrances(&self, fight: &Fight) -> Vec<f32> {
+        let mut hindrances = fight
+            .get_entities()
+            .iter()
+            .filter_map(|monster| monster.try_borrow().ok())
+            .filter(|monster| monster.team_id == self.team_id && monster.is_alive())
+            .map(|monster| monster.id)
+            .chain(once(self.id))
+            .map(|id| self.hindrance(fight, id))
+            .filter(|hindrance| *hindrance > 0.0)
+            .collect::<Vec<_>>();
+        hindrances.sort_by_key(|hindrance| std::cmp::Reverse(F32(*hindrance)));
+        hindrances
+    }
+    //Missing hp of the living allies, and self, most wounded first, with the weight of healing them.
+    //Healing an ally about to fall is worth twice as much.
+    fn wounds(&self, fight: &Fight) -> Vec<(i32, f32)> {
+        let wound = |monster: &Monster| {
+            let missing = monster.max_hp() - monster.hp();
+            let weight = if monster.hp() * 4 <= monster.max_hp() {
+                2.0
+            } else {
+                1.0
+            };
+            (missing, weight)
+        };
+        //The monster playing is already borrowed by the fight
+        let mut wounds = fight
+            .get_entities()
+            .iter()
+            .filter_map(|monster| monster.try_borrow().ok())
+            .filter(|monster| monster.team_id == self.team_id && monster.is_alive())
+            .map(|monster| wound(&monster))
+            .chain(once(wound(self)))
+            .filter(|(missing, _)| *missing > 0)
+            .collect::<Vec<_>>();
+        wounds.sort_by_key(|(missing, weight)| std::cmp::Reverse(F32(*missing as f32 * weight)));
+        wounds
+    }
+    /// Choose the action to play and spend its resources, return it with its name.
+    pub fn take_action(&mut self, fight: &Fight) -> Option<(String, ActionStruct)> {
+        self.choose_action(fight, false)
+    }
+    /// Choose the legendary action to play at the end of the turn of another creature.
+    pub fn take_legendary_action(&mut self, fight: &Fight) -> Option<(String, ActionStruct)> {
+        self.choose_action(fight, true)
+    }
+    fn choose_action(&mut self, fight: &Fight, legendary: bool) -> Option<(String, ActionStruct)> {
+        let wounds = self.wounds(fight);
+        let hindrances = self.hindrances(fight);
+        let temp_hp = self.temp_hp();
         let resources = &mut self.resources;
+        let free_actions = &mut self.free_actions;
 
         let available_action = self
             .actions
             .iter_mut()
             .filter_map(|action| {
-                if action.1.is_available(&resources) {
-                    Some(action)
+                if action.1.is_legendary() == legendary
+                    && action.1.is_available(resources)
+                    && action.1.has_charges()
+                    && !(action.1.is_free() && free_actions.contains(action.0))
+                    //The reactions are only played when they are triggered
+                    && !action.1.resources().contains(&Resource::Reaction)
+                {
+                    //Value the hp healed like the hp inflicted
+                    let value = action.1.average_dammage()
+                        + action.1.average_effect()
+                        + action.1.healing_value(&wounds)
+                        + action.1.temp_hp_value(temp_hp)
+                        + action.1.dispel_value(&hindrances);
+                    Some((value, action))
                 } else {
                     None
                 }
             })
-            .max_by(|a, b| F32(a.1.average_dammage()).cmp(&F32(b.1.average_dammage())));
-        if let Some((name, action)) = available_action {
+            .filter(|(value, _)| *value > 0.0)
+            .max_by(|(a_value, a), (b_value, b)| {
+                //Break ties on the name so the choice doesn't depend on the HashMap order
+                F32(*a_value)
+                    .cmp(&F32(*b_value))
+                    .then_with(|| b.0.cmp(a.0))
+            });
+        if let Some((_, (name, action))) = available_action {
             action.consume_resources(resources);
             action.use_charge();
+            if action.is_attack_action() {
+                resources.insert(Resource::AttackAction, 1);
+            }
+            if action.is_free() {
+                free_actions.push(name.clone());
+            }
 
             println!("Use {name}");
-            return Some(action.clone());
+            Some((name.clone(), action.clone()))
         } else {
             None
         }
     }
     pub fn new_turn(&mut self) {
+        let legendary_actions = self.legendary_actions;
         let resources = &mut self.resources;
+        resources
+            .entry(Resource::LegendaryAction)
+            .and_modify(|e| *e = legendary_actions);
         resources.entry(Resource::Action).and_modify(|e| *e = 1);
         resources
             .entry(Resource::BonusAction)
@@ -140,6 +529,89 @@ impl Monster {
         resources
             .entry(Resource::SpellAction)
             .and_modify(|e| *e = 1);
+        resources.entry(Resource::Reaction).and_modify(|e| *e = 1);
+        resources.remove(&Resource::AttackAction);
+        self.free_actions.clear();
+    }
+    /// Roll the recharge of the used actions at the start of the turn, like a breath weapon.
+    pub fn roll_recharges<R: Rng + ?Sized>(&mut self, rng: &mut R) {
+        //Roll in the order of the names so the fight doesn't depend on the HashMap order
+        let mut names = self.actions.keys().cloned().collect::<Vec<_>>();
+        names.sort();
+        for name in names {
+            if self.actions.get_mut(&name).unwrap().roll_recharge(rng) {
+                eprintln!("{} recharges {name}", self.name);
+            }
+        }
+    }
+    /// Spend a legendary resistance to succeed a failed save, if the `stakes` are worth it:
+    /// an effect taking its turns or a quarter of its hp.
+    pub fn legendary_resistance(&mut self, stakes: Stakes) -> bool {
+        let worth = stakes.effect >= Condition::Incapacitated.value() || stakes.damage >= self.hp() as f32 / 4.0;
+        match self.resources.get_mut(&Resource::LegendaryResistance) {
+            Some(left) if *left > 0 && worth => {
+                *left -= 1;
+                eprintln!("{} uses a legendary resistance ({left} left)", self.name);
+                true
+            }
+            _ => false,
+        }
+    }
+    pub fn actions(&self) -> &HashMap<String, ActionStruct> {
+        &self.actions
+    }
+    //Spend the first available reaction accepted by `choose`
+    fn react<T>(&mut self, choose: impl Fn(&ReactionKind) -> Option<T>) -> Option<(Reaction, T)> {
+        if !self.is_conscious() || self.is_incapacitated() {
+            return None;
+        }
+        let resources = &mut self.resources;
+        let (reaction, chosen) = self
+            .reactions
+            .iter_mut()
+            .filter(|reaction| reaction.is_available(resources))
+            .find_map(|reaction| choose(reaction.kind()).map(|chosen| (reaction, chosen)))?;
+        reaction.spend(resources);
+        eprintln!("{} reacts with {}", self.name, reaction.name());
+        Some((reaction.clone(), chosen))
+    }
+    /// Raise the armor class against a hit exceeding it by `margin`, if the raise turns it into a miss.
+    pub fn armor_class_reaction(&mut self, margin: i32) -> Option<(Reaction, i32)> {
+        self.react(|kind| match kind {
+            ReactionKind::ArmorClass(bonus) if *bonus > margin => Some(*bonus),
+            _ => None,
+        })
+    }
+    /// Components to apply to the creature that damaged the monster.
+    pub fn retaliation(&mut self) -> Option<(Reaction, Vec<ActionComponent>)> {
+        self.react(|kind| match kind {
+            ReactionKind::Retaliate(components) => Some(components.clone()),
+            _ => None,
+        })
+    }
+    /// Try to cancel a spell of `level`, the cantrips aren't worth it.
+    /// Return the highest level cancelled without check and the modifier of the check.
+    pub fn counterspell(&mut self, level: i32) -> Option<(Reaction, (i32, i32))> {
+        if level == 0 {
+            return None;
+        }
+        self.react(|kind| match kind {
+            ReactionKind::Counter {
+                level,
+                ability_modifier,
+            } => Some((*level, *ability_modifier)),
+            _ => None,
+        })
+    }
+    /// Actions played by the lair of the monster on initiative count 20.
+    pub fn lair_actions(&self) -> &HashMap<String, ActionStruct> {
+        &self.lair_actions
+    }
+    pub fn short_rest(&mut self) {
+        self.actions.values_mut().for_each(ActionStruct::short_rest);
+    }
+    pub fn long_rest(&mut self) {
+        self.actions.values_mut().for_each(ActionStruct::long_rest);
     }
     pub fn highest_spell_slot(&self) -> i32 {
         self.resources
@@ -160,15 +632,28 @@ impl Monster {
 pub struct MonsterStats {
     ability: [i8; mem::variant_count::<Ability>()],
     saving_throws: [i8; mem::variant_count::<Ability>()],
+    //Initiative modifier
     initiative: i8,
+    fixed_initiative: Option<i32>,
     armor_class: i8,
     hp: i32,
     max_hp: i32,
+    //Temporary hit points, lost before hp
+    temp_hp: i32,
+    resistances: Vec<DamageType>,
+    immunities: Vec<DamageType>,
+    vulnerabilities: Vec<DamageType>,
+    nonmagical_resistances: Vec<DamageType>,
+    nonmagical_immunities: Vec<DamageType>,
 }
 
 impl MonsterStats {
-    fn from_template(builder: &TemplateBuilder, template: &MonsterStatsTemplate) -> Self {
-        let hp = template.hp.roll();
+    fn from_template<R: Rng + ?Sized>(
+        _builder: &TemplateBuilder,
+        template: &MonsterStatsTemplate,
+        rng: &mut R,
+    ) -> Self {
+        let hp = template.hp.roll(rng);
         let mut ability = [0; mem::variant_count::<Ability>()];
         ability[Ability::Strength as usize] = template.abilities.strength;
         ability[Ability::Dexterity as usize] = template.abilities.dexterity;
@@ -193,15 +678,26 @@ impl MonsterStats {
         Self {
             ability,
             saving_throws,
-            initiative: template.initiative,
+            //Dexterity modifier, and the bonus of features like Alert
+            initiative: modifier(template.abilities.dexterity.into()) as i8 + template.initiative,
+            fixed_initiative: None,
             armor_class: template.armor_class,
             hp,
             max_hp: hp,
+            temp_hp: 0,
+            resistances: template.resistances.clone(),
+            immunities: template.immunities.clone(),
+            vulnerabilities: template.vulnerabilities.clone(),
+            nonmagical_resistances: template.nonmagical_resistances.clone(),
+            nonmagical_immunities: template.nonmagical_immunities.clone(),
         }
     }
     pub fn hp(&self) -> i32 {
         self.hp
     }
+    pub fn max_hp(&self) -> i32 {
+        self.max_hp
+    }
     pub fn set_max_hp(&mut self, amount: i32) {
         self.max_hp = amount;
         self.hp = self.hp.min(self.max_hp);
@@ -210,11 +706,26 @@ impl MonsterStats {
         self.hp = amount;
         self.max_hp = self.max_hp.max(self.hp);
     }
-    pub fn decrease_hp(&mut self, amount: i32) {
-        self.hp -= amount;
+    //Return the damage left over once the hp reach 0
+    pub fn decrease_hp(&mut self, amount: i32) -> i32 {
+        let absorbed = amount.min(self.temp_hp);
+        self.temp_hp -= absorbed;
+        self.hp -= amount - absorbed;
+        let overflow = -self.hp;
         if self.hp < 0 {
             self.hp = 0;
         }
+        overflow.max(0)
+    }
+    pub fn temp_hp(&self) -> i32 {
+        self.temp_hp
+    }
+    //Temporary hit points don't stack, the highest amount is kept
+    pub fn add_temp_hp(&mut self, amount: i32) {
+        self.temp_hp = self.temp_hp.max(amount);
+    }
+    pub fn remove_temp_hp(&mut self) {
+        self.temp_hp = 0;
     }
     pub fn increase_hp(&mut self, amount: i32) {
         self.hp += amount;
@@ -228,4 +739,29 @@ impl MonsterStats {
     pub fn save_mod(&self, ability: Ability) -> i32 {
         self.saving_throws[ability as usize] as i32
     }
+    pub fn ability_mod(&self, ability: Ability) -> i32 {
+        modifier(self.ability[ability as usize].into())
+    }
+    //`resist_all` for the conditions granting resistance to every damage, like Petrified
+    fn damage_taken(&self, damage: Damage, resist_all: bool) -> i32 {
+        let damage_type = damage.damage_type();
+        let nonmagical = !damage.is_magical();
+        if self.immunities.contains(&damage_type)
+            || (nonmagical && self.nonmagical_immunities.contains(&damage_type))
+        {
+            return 0;
+        }
+        //Resistance is applied before vulnerability, so having both still loses the odd point
+        let mut amount = damage.amount();
+        if resist_all
+            || self.resistances.contains(&damage_type)
+            || (nonmagical && self.nonmagical_resistances.contains(&damage_type))
+        {
+            amount /= 2;
+        }
+        if self.vulnerabilities.contains(&damage_type) {
+            amount *= 2;
+        }
+        amount
+    }
 }
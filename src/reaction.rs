@@ -0,0 +1,71 @@
+//! Reactions, played out of turn by spending the reaction of the creature.
+//! There are no opportunity attacks: the creatures don't move in the simulation.
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::action::ActionComponent;
+use crate::resource::{consume_resources, has_resources, Charge, Resource};
+use crate::spell::{School, SpellInfo};
+
+/// What a reaction does, it also tells what triggers it.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum ReactionKind {
+    //When hit by an attack, added to the armor class against it and until the start of its next turn, like Shield
+    ArmorClass(i32),
+    //After taking damage, applied to the creature that dealt it, like Hellish Rebuke
+    Retaliate(Vec<ActionComponent>),
+    //When an enemy casts a spell, cancel it if its level is at most `level`, otherwise on an ability check
+    //of DC 10 + its level, like Counterspell
+    Counter {
+        level: i32,
+        #[serde(default)]
+        ability_modifier: i32,
+    },
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Reaction {
+    name: String,
+    reaction: ReactionKind,
+    //Spent with the reaction, like a spell slot
+    #[serde(default)]
+    resources: Vec<Resource>,
+    #[serde(default)]
+    charges: Charge,
+    //Only for the spells, cast at the level of the spell slot in the resources
+    #[serde(default)]
+    school: Option<School>,
+}
+impl Reaction {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+    pub fn kind(&self) -> &ReactionKind {
+        &self.reaction
+    }
+    /// Spell cast with the reaction, `None` if it isn't a spell.
+    pub fn spell(&self) -> Option<SpellInfo> {
+        let school = self.school?;
+        let level = self.resources.iter().find_map(|resource| match resource {
+            Resource::Spell(level) => Some(*level),
+            _ => None,
+        });
+        Some(SpellInfo {
+            name: self.name.clone(),
+            school,
+            level: level.unwrap_or(0),
+            concentration: false,
+        })
+    }
+    pub fn is_available(&self, resources: &HashMap<Resource, i32>) -> bool {
+        self.charges.is_available()
+            && resources.get(&Resource::Reaction).is_some_and(|qty| *qty > 0)
+            && has_resources(&self.resources, resources)
+    }
+    /// Spend the reaction of the creature and the resources of the reaction.
+    pub fn spend(&mut self, resources: &mut HashMap<Resource, i32>) {
+        consume_resources(&[Resource::Reaction], resources);
+        consume_resources(&self.resources, resources);
+        self.charges.use_charge();
+    }
+}
@@ -1,4 +1,6 @@
+use rand::Rng;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 
 #[derive(Default, Copy, Clone, Debug, Eq, PartialEq, Hash, Serialize, Deserialize)]
 pub enum Resource {
@@ -9,11 +11,153 @@ pub enum Resource {
     SpellAction,
     Ki,
     Spell(i32),
+    //Points spent at the end of the turns of the other creatures
+    LegendaryAction,
+    //Uses left of the Legendary Resistance, turning a failed save into a success
+    LegendaryResistance,
+    Reaction,
+    //Given by taking the Attack action, needed by the bonus attacks like the off-hand ones
+    AttackAction,
 }
 
+/// Part of the turn an action takes. The reactions aren't actions played on the turn
+/// of the creature, they are in the reactions of the monster.
+#[derive(Default, Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub enum ActionCost {
+    #[default]
+    Action,
+    BonusAction,
+    //Costs nothing, it is played once per turn
+    Free,
+}
+impl ActionCost {
+    pub fn resources(&self) -> Vec<Resource> {
+        match self {
+            Self::Action => vec![Resource::Action],
+            Self::BonusAction => vec![Resource::BonusAction],
+            Self::Free => vec![],
+        }
+    }
+}
+
+/// Whether `resources` has all the `needed` ones, a resource may be needed several times.
+pub fn has_resources(needed: &[Resource], resources: &HashMap<Resource, i32>) -> bool {
+    needed.iter().all(|resource| {
+        let count = needed.iter().filter(|r| *r == resource).count() as i32;
+        resources.get(resource).is_some_and(|qty| *qty >= count)
+    })
+}
+pub fn consume_resources(needed: &[Resource], resources: &mut HashMap<Resource, i32>) {
+    for resource in needed {
+        resources.entry(*resource).and_modify(|qty| *qty -= 1);
+    }
+}
+
+/// How many times an action can be used. The `used` counts are the state of the fight,
+/// they are never written in the database.
 #[derive(Default, Copy, Clone, Debug, PartialEq, Hash, Serialize, Deserialize)]
 pub enum Charge {
     #[default]
     Infinite,
+    //Never regained, like a potion
     Limited(i32),
+    //Regained at the start of the turn on a d6 of at least `roll`, "Recharge 5–6" is 5
+    Recharge {
+        roll: i32,
+        #[serde(skip)]
+        used: bool,
+    },
+    ShortRest {
+        uses: i32,
+        #[serde(skip)]
+        used: i32,
+    },
+    //Also the "X/day" actions
+    #[serde(alias = "PerDay")]
+    LongRest {
+        uses: i32,
+        #[serde(skip)]
+        used: i32,
+    },
+}
+impl Charge {
+    pub fn is_available(&self) -> bool {
+        match self {
+            Self::Infinite => true,
+            Self::Limited(qty) => *qty > 0,
+            Self::Recharge { used, .. } => !used,
+            Self::ShortRest { uses, used } | Self::LongRest { uses, used } => used < uses,
+        }
+    }
+    pub fn use_charge(&mut self) {
+        match self {
+            Self::Infinite => {}
+            Self::Limited(qty) => *qty -= 1,
+            Self::Recharge { used, .. } => *used = true,
+            Self::ShortRest { used, .. } | Self::LongRest { used, .. } => *used += 1,
+        }
+    }
+    /// Roll the d6 of a used recharge at the start of the turn, return whether the action is available again.
+    pub fn roll_recharge<R: Rng + ?Sized>(&mut self, rng: &mut R) -> bool {
+        if let Self::Recharge { roll, used: used @ true } = self {
+            *used = rng.gen_range(1..=6) < *roll;
+            !*used
+        } else {
+            false
+        }
+    }
+    pub fn short_rest(&mut self) {
+        if let Self::ShortRest { used, .. } = self {
+            *used = 0;
+        }
+    }
+    /// A long rest also regains the uses of the short rest and recharge actions.
+    pub fn long_rest(&mut self) {
+        match self {
+            Self::Recharge { used, .. } => *used = false,
+            Self::ShortRest { used, .. } | Self::LongRest { used, .. } => *used = 0,
+            Self::Infinite | Self::Limited(_) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+    #[test]
+    fn recharge() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut breath: Charge = serde_json::from_str(r#"{"Recharge": {"roll": 5}}"#).unwrap();
+        assert!(breath.is_available());
+        //Nothing to recharge
+        assert!(!breath.roll_recharge(&mut rng));
+        breath.use_charge();
+        assert!(!breath.is_available());
+        //A third of the rolls recharge it
+        let rolls = (0..1000)
+            .filter(|_| {
+                let mut charge = breath;
+                charge.roll_recharge(&mut rng)
+            })
+            .count();
+        assert!((280..390).contains(&rolls));
+
+        let mut per_day: Charge = serde_json::from_str(r#"{"PerDay": {"uses": 2}}"#).unwrap();
+        per_day.use_charge();
+        per_day.short_rest();
+        per_day.use_charge();
+        assert!(!per_day.is_available());
+        per_day.long_rest();
+        assert!(per_day.is_available());
+    }
+    #[test]
+    fn action_cost() {
+        let cost: ActionCost = serde_json::from_str(r#""BonusAction""#).unwrap();
+        assert_eq!(cost.resources(), [Resource::BonusAction]);
+        assert!(ActionCost::Free.resources().is_empty());
+        //The reactions are declared with the reactions of the monster
+        assert!(serde_json::from_str::<ActionCost>(r#""Reaction""#).is_err());
+    }
 }
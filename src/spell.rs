@@ -0,0 +1,24 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Default, Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub enum School {
+    #[default]
+    Abjuration,
+    Conjuration,
+    Divination,
+    Enchantment,
+    Evocation,
+    Illusion,
+    Necromancy,
+    Transmutation,
+}
+
+/// What makes an action a spell, kept on the action and on the effects it creates.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SpellInfo {
+    pub name: String,
+    pub school: School,
+    //Level it is cast at, 0 for a cantrip
+    pub level: i32,
+    pub concentration: bool,
+}
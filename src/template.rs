@@ -2,11 +2,15 @@ pub use self::monster::MonsterTemplate;
 pub use self::stats::StatsTemplate;
 pub use self::stats::MonsterStatsTemplate;
 pub use self::action::ActionTemplate;
+pub use self::action::DamageTemplate;
+pub use self::effect::EffectTemplate;
+pub use self::effect::RepeatSave;
 pub use self::spell::SpellTemplate;
 pub use self::template_builder::TemplateBuilder;
 
 mod monster;
 mod action;
+mod effect;
 mod stats;
 mod spell;
 mod template_builder;
@@ -1,6 +1,10 @@
 use serde::{Deserialize, Serialize};
 
+use crate::action::ActionComponent;
+use crate::damage::DamageType;
+use crate::dice::Dice;
 use crate::formula::Formula;
+use crate::resource::{ActionCost, Charge, Resource};
 use crate::utils::*;
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -10,23 +14,95 @@ pub enum ActionTemplate {
         attack_modifier: i32,
         #[serde(deserialize_with = "string_or_struct")]
         dammage: Formula,
+        damage_type: DamageType,
+        //Damage of other types dealt on a hit, like the fire of a flame tongue
+        #[serde(default)]
+        additional_damage: Vec<DamageTemplate>,
+        #[serde(default)]
+        magical: bool,
         target_count: i32,
+        #[serde(default)]
+        critical_range: Option<i32>,
+        #[serde(default)]
+        critical_extra_dice: Option<i32>,
+        //Ranged attacks are made from further than 5 ft
+        #[serde(default)]
+        ranged: bool,
+        //Components applied on a hit after the damage, like the paralysis of a ghoul's claws
+        #[serde(default)]
+        on_hit: Vec<ActionComponent>,
+        #[serde(default)]
+        charges: Charge,
+        #[serde(default)]
+        cost: ActionCost,
+        //Spent on top of the cost, like the Ki of a monk
+        #[serde(default)]
+        resources: Vec<Resource>,
+        //Bonus attack of two-weapon fighting after the Attack action, without the ability modifier to the damage
+        #[serde(default)]
+        off_hand: bool,
+        //Uses the best of strength and dexterity, like a dagger
+        #[serde(default)]
+        finesse: bool,
     },
     MultiAttack {
         name: String,
         attacks: Vec<ActionTemplate>,
+        #[serde(default)]
+        charges: Charge,
+        #[serde(default)]
+        cost: ActionCost,
+        #[serde(default)]
+        resources: Vec<Resource>,
+    },
+    //An action costing `cost` legendary actions, played at the end of the turn of another creature
+    Legendary {
+        cost: i32,
+        action: Box<ActionTemplate>,
+    },
+    //An action made of its components, like a breath weapon
+    Feature {
+        name: String,
+        components: Vec<ActionComponent>,
+        #[serde(default)]
+        charges: Charge,
+        #[serde(default)]
+        cost: ActionCost,
+        #[serde(default)]
+        resources: Vec<Resource>,
     },
     Spell {
         name: String,
         spell_attack : i32,
         spell_dc: i32,
+        //Spellcasting ability modifier, added to the healing of spells like Cure Wounds
+        #[serde(default)]
+        spell_modifier: i32,
     },
 }
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DamageTemplate {
+    #[serde(deserialize_with = "string_or_struct")]
+    pub damage: Formula,
+    pub damage_type: DamageType,
+}
 impl Default for ActionTemplate {
     fn default() -> Self {
         Self::Attack {
             attack_modifier: 0,
-            dammage: Formula::from("1d4"),
+            dammage: Formula::from(Dice::new(1, 4)),
+            damage_type: DamageType::default(),
+            additional_damage: Vec::new(),
+            magical: false,
+            critical_range: None,
+            critical_extra_dice: None,
+            ranged: false,
+            on_hit: Vec::new(),
+            charges: Charge::Infinite,
+            cost: ActionCost::Action,
+            resources: Vec::new(),
+            off_hand: false,
+            finesse: false,
             target_count: 1,
             name: "Default ActionTemplate".to_string(),
         }
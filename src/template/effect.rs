@@ -0,0 +1,34 @@
+use serde::{Deserialize, Serialize};
+
+use crate::ability::Ability;
+use crate::condition::Condition;
+use crate::duration::Duration;
+use crate::formula::Formula;
+use crate::modifier::Modifier;
+
+#[derive(Default, Clone, Debug, Serialize, Deserialize)]
+pub struct EffectTemplate {
+    pub name: String,
+    #[serde(default)]
+    pub conditions: Vec<Condition>,
+    #[serde(default)]
+    pub modifiers: Vec<Modifier>,
+    #[serde(default)]
+    pub durations: Vec<Duration>,
+    //Saving throw the target repeats at the end of each of its turns to end the effect
+    #[serde(default)]
+    pub repeat_save: Option<RepeatSave>,
+    //Ends when the source loses its concentration, set by the concentration spells
+    #[serde(default)]
+    pub concentration: bool,
+    //Temporary hit points given to the target, lost when the effect ends, like Armor of Agathys
+    #[serde(default)]
+    pub temp_hp: Option<Formula>,
+}
+
+#[derive(Default, Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct RepeatSave {
+    pub ability: Ability,
+    #[serde(default)]
+    pub save_dc: i32,
+}
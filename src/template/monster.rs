@@ -1,3 +1,5 @@
+use crate::modifier::Modifier;
+use crate::reaction::Reaction;
 use crate::resource::Resource;
 use crate::template::ActionTemplate;
 use crate::template::MonsterStatsTemplate;
@@ -10,4 +12,30 @@ pub struct MonsterTemplate {
     pub actions: Vec<ActionTemplate>,
     #[serde(default)]
     pub resources: Vec<Resource>,
+    //Lowest natural roll of the weapon attacks scoring a critical hit, 19 for a Champion
+    #[serde(default)]
+    pub critical_range: Option<i32>,
+    //Weapon dice added to critical hits by Savage Attacks or Brutal Critical
+    #[serde(default)]
+    pub critical_extra_dice: i32,
+    //Player characters roll death saves at 0 hp instead of dying
+    #[serde(default)]
+    pub player: bool,
+    //Modifiers the monster always has, from feats like War Caster or Resilient
+    #[serde(default)]
+    pub modifiers: Vec<Modifier>,
+    //Points of legendary actions regained at the start of each of its turns
+    #[serde(default)]
+    pub legendary_actions: i32,
+    //Legendary Resistance (N/Day)
+    #[serde(default)]
+    pub legendary_resistances: i32,
+    //Played by its lair on initiative count 20
+    #[serde(default)]
+    pub lair_actions: Vec<ActionTemplate>,
+    #[serde(default)]
+    pub reactions: Vec<Reaction>,
+    //Highest level of the spells that can't affect the monster, like Limited Magic Immunity
+    #[serde(default)]
+    pub spell_immunity: Option<i32>,
 }
@@ -3,12 +3,17 @@ use crate::action::ActionStruct;
 use crate::resource::Charge;
 use crate::resource::Resource;
 use crate::formula::Formula;
+use crate::spell::{School, SpellInfo};
 use crate::utils::*;
 use serde::{Deserialize, Serialize};
-use std::ops::{Add, AddAssign, Div, DivAssign, Mul, MulAssign, Sub, SubAssign};
+use std::ops::Mul;
 
 #[derive(Default, Clone, Debug, Serialize, Deserialize)]
 pub struct SpellTemplate {
+    //Set from the key of the spell in the database
+    #[serde(skip)]
+    name: String,
+    school: School,
     charges: Charge,
     resources: Vec<Resource>,
     level: i32,
@@ -23,13 +28,19 @@ pub struct SpellTemplate {
     spell_dc: i32,
     #[serde(default)]
     spell_attack: i32,
+    #[serde(default)]
+    spell_modifier: i32,
+    #[serde(default)]
+    concentration: bool,
 }
-#[derive(Default, Copy, Clone, Debug, Serialize, Deserialize)]
+#[derive(Default, Clone, Debug, Serialize, Deserialize)]
 pub struct ActionComponentModifier {
     #[serde(default)]
     target_count: i32,
     #[serde(deserialize_with = "string_or_struct", default)]
     damage: Formula,
+    #[serde(deserialize_with = "string_or_struct", default)]
+    heal: Formula,
 }
 impl Mul<i32> for ActionComponentModifier {
     type Output = Self;
@@ -37,11 +48,16 @@ impl Mul<i32> for ActionComponentModifier {
         Self {
             target_count: self.target_count * other,
             damage: self.damage * other,
+            heal: self.heal * other,
         }
     }
 }
 
 impl SpellTemplate {
+    pub fn name(&mut self, name: &str) -> &mut Self {
+        self.name = name.to_string();
+        self
+    }
     pub fn spell_attack(&mut self, amount: i32) -> &mut Self {
         self.spell_attack = amount;
         self
@@ -50,6 +66,10 @@ impl SpellTemplate {
         self.spell_dc = amount;
         self
     }
+    pub fn spell_modifier(&mut self, amount: i32) -> &mut Self {
+        self.spell_modifier = amount;
+        self
+    }
     pub fn upcast(&mut self, lvl: i32) -> &mut Self {
         self.upcast_level = lvl;
         self
@@ -60,18 +80,39 @@ impl SpellTemplate {
     pub fn build(&self) -> ActionStruct {
         let mut action = ActionStruct::default();
         action.set_charge(self.charges);
+        let level = self.level + self.upcast_level;
+        action.set_spell(SpellInfo {
+            name: self.name.clone(),
+            school: self.school,
+            level,
+            concentration: self.concentration,
+        });
         for rsce in &self.resources {
             action.add_resource(*rsce);
         }
-        action.add_resource(Resource::SpellAction);
-        action.add_resource(Resource::Spell(self.level + self.upcast_level));
+        //After a bonus action spell only a cantrip can be cast with the action, and the reverse:
+        //the leveled spells and the bonus action spells share the SpellAction of the turn
+        if level > 0 || self.resources.contains(&Resource::BonusAction) {
+            action.add_resource(Resource::SpellAction);
+        }
+        //Cantrips don't use a spell slot
+        if level > 0 {
+            action.add_resource(Resource::Spell(level));
+        }
         for (comp, upcast) in &self.components {
-            let upcast = *upcast * self.upcast_level;
+            let upcast = upcast.clone() * self.upcast_level;
             let mut new_comp = comp.clone();
             new_comp.increase_damage(&upcast.damage);
+            new_comp.increase_heal(&upcast.heal);
             new_comp.increase_target_count(upcast.target_count);
             new_comp.set_save_dc(self.spell_dc);
             new_comp.set_hit_roll(self.spell_attack);
+            new_comp.set_spell_modifier(self.spell_modifier);
+            new_comp.set_magical();
+            new_comp.set_spell_level(level);
+            if self.concentration {
+                new_comp.set_concentration();
+            }
             action.add_component(new_comp);
         }
         for _ in 0..self.upcast_level {
@@ -79,6 +120,12 @@ impl SpellTemplate {
                 let mut new_comp = comp.clone();
                 new_comp.set_save_dc(self.spell_dc);
                 new_comp.set_hit_roll(self.spell_attack);
+                new_comp.set_spell_modifier(self.spell_modifier);
+                new_comp.set_magical();
+                new_comp.set_spell_level(level);
+                if self.concentration {
+                    new_comp.set_concentration();
+                }
                 action.add_component(new_comp);
             }
         }
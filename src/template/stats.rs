@@ -1,3 +1,4 @@
+use crate::damage::DamageType;
 use crate::formula::Formula;
 use crate::utils::*;
 use serde::{Deserialize, Serialize};
@@ -6,10 +7,23 @@ use serde::{Deserialize, Serialize};
 pub struct MonsterStatsTemplate {
     pub abilities: StatsTemplate,
     pub saving_throws: StatsTemplate,
+    //Bonus added to the dexterity modifier for the initiative, like the +5 of Alert
+    #[serde(default)]
     pub initiative: i8,
     pub armor_class: i8,
     #[serde(deserialize_with = "string_or_struct")]
     pub hp: Formula,
+    #[serde(default)]
+    pub resistances: Vec<DamageType>,
+    #[serde(default)]
+    pub immunities: Vec<DamageType>,
+    #[serde(default)]
+    pub vulnerabilities: Vec<DamageType>,
+    //Only against the damage of nonmagical attacks
+    #[serde(default)]
+    pub nonmagical_resistances: Vec<DamageType>,
+    #[serde(default)]
+    pub nonmagical_immunities: Vec<DamageType>,
 }
 
 #[derive(Default, Clone, Copy, Debug, Serialize, Deserialize)]
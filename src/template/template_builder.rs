@@ -1,14 +1,18 @@
+use crate::modifier::Modifier;
 use crate::monster::Monster;
 use crate::template::MonsterTemplate;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
 use std::collections::HashMap;
 
 use super::SpellTemplate;
 
-#[derive(Default, Clone, Debug)]
+#[derive(Clone, Debug)]
 pub struct TemplateBuilder {
     monster_database: HashMap<String, MonsterTemplate>,
     spell_database: HashMap<String, SpellTemplate>,
     current_monster: Option<Monster>,
+    rng: StdRng,
 }
 
 impl TemplateBuilder {
@@ -20,18 +24,31 @@ impl TemplateBuilder {
                 .collect(),
             spell_database: spells,
             current_monster: None,
+            rng: StdRng::from_entropy(),
         }
     }
+    /// Seed the dice rolled while building monsters (hit points, ...).
+    pub fn seed(&mut self, seed: u64) -> &mut Self {
+        self.rng = StdRng::seed_from_u64(seed);
+        self
+    }
     pub fn create(&mut self, name: &str) -> &mut Self {
         assert!(self.monster_database.contains_key(name));
         let template = self.monster_database.get(name).unwrap();
-        self.current_monster = Some(Monster::from_template(self, template));
+        let mut rng = self.rng.clone();
+        self.current_monster = Some(Monster::from_template(self, template, &mut rng));
+        self.rng = rng;
         self
     }
+    pub fn get_monster_template(&self, name: &str) -> MonsterTemplate {
+        assert!(self.monster_database.contains_key(name));
+        self.monster_database.get(name).unwrap().clone()
+    }
     pub fn get_spell_template(&self, name: &str) -> SpellTemplate {
         assert!(self.spell_database.contains_key(name));
-        let template = self.spell_database.get(name).unwrap();
-        template.clone()
+        let mut template = self.spell_database.get(name).unwrap().clone();
+        template.name(name);
+        template
     }
     pub fn team(&mut self, team: i32) -> &mut Self {
         assert!(self.current_monster.is_some());
@@ -47,6 +64,38 @@ impl TemplateBuilder {
         }
         self
     }
+    /// Start the fight with `temp_hp` temporary hit points.
+    pub fn temp_hp(&mut self, temp_hp: i32) -> &mut Self {
+        assert!(self.current_monster.is_some());
+        if let Some(monster) = &mut self.current_monster {
+            monster.add_temp_hp(temp_hp);
+        }
+        self
+    }
+    /// Make the monster a player character, rolling death saves at 0 hp.
+    pub fn player(&mut self, player: bool) -> &mut Self {
+        assert!(self.current_monster.is_some());
+        if let Some(monster) = &mut self.current_monster {
+            monster.set_player(player);
+        }
+        self
+    }
+    /// Give a modifier from a feat or a trait, like the advantage of War Caster on concentration saves.
+    pub fn modifier(&mut self, modifier: Modifier) -> &mut Self {
+        assert!(self.current_monster.is_some());
+        if let Some(monster) = &mut self.current_monster {
+            monster.add_modifier(modifier);
+        }
+        self
+    }
+    /// Use a fixed initiative instead of rolling it at the start of the fight.
+    pub fn initiative(&mut self, initiative: i32) -> &mut Self {
+        assert!(self.current_monster.is_some());
+        if let Some(monster) = &mut self.current_monster {
+            monster.set_initiative(initiative);
+        }
+        self
+    }
     pub fn build(&mut self) -> Monster {
         if let Some(monster) = self.current_monster.take().to_owned() {
             monster
@@ -55,3 +104,8 @@ impl TemplateBuilder {
         }
     }
 }
+impl Default for TemplateBuilder {
+    fn default() -> Self {
+        Self::new(Vec::new(), HashMap::new())
+    }
+}
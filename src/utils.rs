@@ -3,12 +3,12 @@ use std::marker::PhantomData;
 
 use serde::de::{self, MapAccess, Visitor};
 use serde::{Deserialize, Deserializer};
-use std::num::ParseIntError;
 use std::str::FromStr;
 
 pub fn string_or_struct<'de, T, D>(deserializer: D) -> Result<T, D::Error>
 where
-    T: Deserialize<'de> + FromStr<Err = ParseIntError>,
+    T: Deserialize<'de> + FromStr,
+    T::Err: fmt::Display,
     D: Deserializer<'de>,
 {
     // This is a Visitor that forwards string types to T's `FromStr` impl and
@@ -20,7 +20,8 @@ where
 
     impl<'de, T> Visitor<'de> for StringOrStruct<T>
     where
-        T: Deserialize<'de> + FromStr<Err = ParseIntError>,
+        T: Deserialize<'de> + FromStr,
+        T::Err: fmt::Display,
     {
         type Value = T;
 
@@ -32,7 +33,7 @@ where
         where
             E: de::Error,
         {
-            Ok(FromStr::from_str(value).unwrap())
+            FromStr::from_str(value).map_err(de::Error::custom)
         }
 
         fn visit_map<M>(self, map: M) -> Result<T, M::Error>
@@ -51,5 +52,19 @@ where
 }
 
 pub fn modifier(score: i32) -> i32 {
-    ((score - (score % 2)) / 2) + 5
+    (score - 10).div_euclid(2)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn ability_modifier() {
+        assert_eq!(modifier(1), -5);
+        assert_eq!(modifier(9), -1);
+        assert_eq!(modifier(10), 0);
+        assert_eq!(modifier(11), 0);
+        assert_eq!(modifier(14), 2);
+        assert_eq!(modifier(22), 6);
+    }
 }